@@ -0,0 +1,40 @@
+struct BackgroundUniform {
+    uv_scale: vec2<f32>,
+    uv_offset: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_background: texture_2d<f32>;
+@group(0) @binding(1)
+var s_background: sampler;
+@group(0) @binding(2)
+var<uniform> background: BackgroundUniform;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let x = f32((in_vertex_index << 1u) & 2u) * 2.0 - 1.0;
+    let y = f32(in_vertex_index & 2u) * 2.0 - 1.0;
+
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.uv * background.uv_scale + background.uv_offset;
+    let color = textureSample(t_background, s_background, uv);
+    if (any(uv < vec2<f32>(0.0, 0.0)) || any(uv > vec2<f32>(1.0, 1.0))) {
+        discard;
+    }
+    return color;
+}
@@ -0,0 +1,71 @@
+struct Bloom {
+    threshold: f32,
+    intensity: f32,
+    texel_size: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let x = f32((in_vertex_index << 1u) & 2u) * 2.0 - 1.0;
+    let y = f32(in_vertex_index & 2u) * 2.0 - 1.0;
+
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+
+    return out;
+}
+
+// Keep only the parts of the frame brighter than the threshold
+@fragment
+fn fs_extract(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.uv);
+    let brightness = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let factor = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color.rgb * factor, 1.0);
+}
+
+// 9-tap separable gaussian
+fn blur(uv: vec2<f32>, dir: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+    var result = textureSampleLevel(t_source, s_source, uv, 0.0).rgb * weights[0];
+    for (var i = 1; i < 5; i = i + 1) {
+        let offset = dir * bloom.texel_size * f32(i);
+        result = result + textureSampleLevel(t_source, s_source, uv + offset, 0.0).rgb * weights[i];
+        result = result + textureSampleLevel(t_source, s_source, uv - offset, 0.0).rgb * weights[i];
+    }
+    return vec4<f32>(result, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_source, s_source, in.uv);
+    let glow = textureSample(t_bloom, s_source, in.uv);
+    return vec4<f32>(scene.rgb + glow.rgb * bloom.intensity, scene.a);
+}
@@ -1,14 +1,33 @@
 use std::time::Duration;
 
 use bytemuck::{Pod, Zeroable};
-use cgmath::{perspective, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
-use wgpu::util::DeviceExt;
+use cgmath::{
+    ortho, perspective, Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix,
+    Vector3,
+};
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, MouseScrollDelta, VirtualKeyCode},
+    event::{ElementState, MouseScrollDelta},
 };
 
-use crate::{input::MovementState, OPENGL_TO_WGPU_MATRIX, SAFE_FRAC_PI_2};
+use crate::{input::Action, OPENGL_TO_WGPU_MATRIX, REVERSE_Z_MATRIX, SAFE_FRAC_PI_2};
+
+/// How far in front of its starting position the camera puts its orbit
+/// target until `set_target` is called.
+const DEFAULT_ORBIT_DISTANCE: f32 = 10.0;
+/// Keeps zooming from pushing the camera through its target.
+const MIN_ORBIT_DISTANCE: f32 = 0.5;
+
+/// How far the camera moves per line scrolled, about a unit per wheel notch.
+pub const DEFAULT_ZOOM_SENSITIVITY: f32 = 2.0;
+/// The closest scrolling brings the camera to its target, far enough to
+/// keep it out of the cloth.
+pub const DEFAULT_MIN_ZOOM_DISTANCE: f32 = 2.0;
+/// The farthest scrolling takes the camera, well inside the far plane.
+pub const DEFAULT_MAX_ZOOM_DISTANCE: f32 = 60.0;
+/// How quickly a scroll is eased in, per second. At 15 most of it lands
+/// within the first handful of frames.
+const ZOOM_EASING: f32 = 15.0;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
@@ -29,17 +48,38 @@ impl CameraUniform {
         }
     }
 
-    // UPDATED!
+    /// The layout every pipeline binds the camera uniform with, at group 0.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        })
+    }
+
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Point3<f32>,
     pub yaw: Rad<f32>,
     pub pitch: Rad<f32>,
+    /// The point `orbit` circles around.
+    pub target: Point3<f32>,
+    /// How far `orbit` keeps the camera from `target`.
+    pub distance: f32,
 }
 
 impl Camera {
@@ -49,11 +89,87 @@ impl Camera {
         Y: Into<Rad<f32>>,
         P: Into<Rad<f32>>,
     {
-        Self {
+        let mut camera = Self {
             position: position.into(),
             yaw: yaw.into(),
             pitch: pitch.into(),
+            target: Point3::new(0.0, 0.0, 0.0),
+            distance: DEFAULT_ORBIT_DISTANCE,
+        };
+        camera.target = camera.position + camera.look_at_vec() * camera.distance;
+        camera
+    }
+
+    /// Orbits around `target` from now on, turning to face it without moving.
+    pub fn set_target(&mut self, target: Point3<f32>) {
+        let to_target = target - self.position;
+        let distance = to_target.magnitude();
+        self.target = target;
+        if distance > 0.0 {
+            let dir = to_target / distance;
+            self.yaw = Rad(dir.z.atan2(dir.x));
+            self.pitch = Rad(dir.y.asin());
         }
+        self.distance = distance.max(MIN_ORBIT_DISTANCE);
+        self.orbit(Rad(0.0), Rad(0.0));
+    }
+
+    /// Turns the camera by the given angles and moves it along the sphere of
+    /// radius `distance` around `target` so it keeps looking at it.
+    pub fn orbit(&mut self, yaw_delta: Rad<f32>, pitch_delta: Rad<f32>) {
+        self.yaw += yaw_delta;
+        self.pitch += pitch_delta;
+
+        // Keep the camera's angle from going too high/low.
+        if self.pitch < -Rad(SAFE_FRAC_PI_2) {
+            self.pitch = -Rad(SAFE_FRAC_PI_2);
+        } else if self.pitch > Rad(SAFE_FRAC_PI_2) {
+            self.pitch = Rad(SAFE_FRAC_PI_2);
+        }
+
+        self.position = self.target - self.look_at_vec() * self.distance;
+    }
+
+    /// Moves the camera towards (negative `delta`) or away from `target`.
+    pub fn zoom(&mut self, delta: f32) {
+        self.distance = (self.distance + delta).max(MIN_ORBIT_DISTANCE);
+        self.orbit(Rad(0.0), Rad(0.0));
+    }
+
+    /// Orbits around the center of the box from `min` to `max`, backing off
+    /// just far enough for all of it to be in view. In orthographic mode the
+    /// projection's height is fitted instead. Returns the new distance.
+    pub fn fit_to_bounds(
+        &mut self,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+        projection: &mut Projection,
+    ) -> f32 {
+        // Fit the box's bounding sphere so it stays in view from any angle
+        let radius = ((max - min).magnitude() / 2.0).max(MIN_ORBIT_DISTANCE);
+        self.target = Point3::from_vec((min + max) / 2.0);
+        self.distance = match projection.mode {
+            ProjectionMode::Perspective { fovy } => {
+                // Whichever of the vertical and horizontal fov is narrower
+                let half_fovy = fovy.0 / 2.0;
+                let half_fovx = (half_fovy.tan() * projection.aspect).atan();
+                radius / half_fovy.min(half_fovx).sin()
+            }
+            ProjectionMode::Orthographic { .. } => {
+                projection.mode = ProjectionMode::Orthographic {
+                    height: 2.0 * radius / projection.aspect.min(1.0),
+                };
+                radius * 2.0
+            }
+        };
+        self.orbit(Rad(0.0), Rad(0.0));
+        self.distance
+    }
+
+    /// Moves the camera and its target together.
+    pub fn pan(&mut self, offset: Vector3<f32>) {
+        self.position += offset;
+        self.target += offset;
     }
 
     pub fn look_at_vec(&self) -> Vector3<f32> {
@@ -62,6 +178,64 @@ impl Camera {
         Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
     }
 
+    /// The direction to the camera's right, parallel to the ground.
+    pub fn right_vec(&self) -> Vector3<f32> {
+        self.look_at_vec().cross(Vector3::unit_y()).normalize()
+    }
+
+    pub fn up_vec(&self) -> Vector3<f32> {
+        self.right_vec().cross(self.look_at_vec()).normalize()
+    }
+
+    /// Where the camera is and how it sees, for putting it back later with
+    /// `set_pose`.
+    pub fn pose(&self, projection: &Projection) -> CameraPose {
+        let projection_pose = match projection.mode {
+            ProjectionMode::Perspective { fovy } => PoseProjection::Perspective {
+                fovy_deg: Deg::from(fovy).0,
+            },
+            ProjectionMode::Orthographic { height } => PoseProjection::Orthographic { height },
+        };
+        CameraPose {
+            position: self.position.into(),
+            yaw: self.yaw.0,
+            pitch: self.pitch.0,
+            distance: self.distance,
+            projection: projection_pose,
+            aspect: projection.aspect,
+        }
+    }
+
+    /// Moves the camera back to `pose`. If the window is now narrower than
+    /// when the pose was taken, the view is widened so everything that was
+    /// in it left to right still is.
+    pub fn set_pose(&mut self, pose: &CameraPose, projection: &mut Projection) {
+        self.position = pose.position.into();
+        self.yaw = Rad(pose.yaw);
+        self.pitch = Rad(pose.pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.distance = pose.distance.max(MIN_ORBIT_DISTANCE);
+        self.target = self.position + self.look_at_vec() * self.distance;
+
+        // A taller window already shows at least as much as before
+        let widen = if pose.aspect.is_finite() && pose.aspect > projection.aspect {
+            pose.aspect / projection.aspect
+        } else {
+            1.0
+        };
+        match pose.projection {
+            PoseProjection::Perspective { fovy_deg } => {
+                let half_fovy = Rad::from(Deg(fovy_deg)).0 / 2.0;
+                let half_fovy = (half_fovy.tan() * widen).atan();
+                projection.set_fovy(Rad(half_fovy * 2.0).into());
+            }
+            PoseProjection::Orthographic { height } => {
+                projection.set_projection_mode(ProjectionMode::Orthographic {
+                    height: height * widen,
+                });
+            }
+        }
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let look_at_vec = self.look_at_vec();
 
@@ -69,29 +243,125 @@ impl Camera {
     }
 }
 
+/// A saved `Camera` and `Projection`, see `Camera::pose`. Angles are in
+/// radians unless the name says otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    /// How far ahead the orbit target is.
+    pub distance: f32,
+    pub projection: PoseProjection,
+    /// Width over height of the window the pose was taken in.
+    pub aspect: f32,
+}
+
+/// `ProjectionMode` as it's saved in a `CameraPose`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PoseProjection {
+    Perspective { fovy_deg: f32 },
+    Orthographic { height: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionMode {
+    Perspective {
+        fovy: Rad<f32>,
+    },
+    /// Parallel projection showing `height` world units top to bottom, for a
+    /// flat, straight-on view of the cloth.
+    Orthographic {
+        height: f32,
+    },
+}
+
+/// The range `Projection::set_fovy` keeps the field of view in.
+const MIN_FOVY: Deg<f32> = Deg(10.0);
+const MAX_FOVY: Deg<f32> = Deg(120.0);
+
 pub struct Projection {
     aspect: f32,
-    fovy: Rad<f32>,
+    mode: ProjectionMode,
     znear: f32,
     zfar: f32,
+    reverse_z: bool,
 }
 
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
             aspect: width as f32 / height as f32,
-            fovy: fovy.into(),
+            mode: ProjectionMode::Perspective { fovy: fovy.into() },
             znear,
             zfar,
+            reverse_z: false,
         }
     }
 
+    pub fn mode(&self) -> ProjectionMode {
+        self.mode
+    }
+
+    pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+        self.mode = mode;
+    }
+
+    /// The vertical field of view, or `None` in orthographic mode.
+    pub fn fovy(&self) -> Option<Rad<f32>> {
+        match self.mode {
+            ProjectionMode::Perspective { fovy } => Some(fovy),
+            ProjectionMode::Orthographic { .. } => None,
+        }
+    }
+
+    /// Switches to a perspective projection with the given vertical field of
+    /// view, clamped to 10°-120°. Narrower is flatter and less distorted.
+    pub fn set_fovy(&mut self, fovy: Deg<f32>) {
+        let fovy = Deg(fovy.0.clamp(MIN_FOVY.0, MAX_FOVY.0));
+        self.mode = ProjectionMode::Perspective { fovy: fovy.into() };
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// Maps the near plane to a depth of 1.0 and the far plane to 0.0. Pair
+    /// this with a 0.0 depth clear and a `GreaterEqual` depth test.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let proj = match self.mode {
+            ProjectionMode::Perspective { fovy } => {
+                perspective(fovy, self.aspect, self.znear, self.zfar)
+            }
+            ProjectionMode::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
+        let proj = OPENGL_TO_WGPU_MATRIX * proj;
+        if self.reverse_z {
+            REVERSE_Z_MATRIX * proj
+        } else {
+            proj
+        }
     }
 }
 #[derive(Debug)]
@@ -105,12 +375,22 @@ pub struct CameraController {
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
+    /// Zoom scrolled in but not yet applied to the camera.
+    pending_zoom: f32,
+    zoom_sensitivity: f32,
+    min_distance: f32,
+    max_distance: f32,
     speed: f32,
-    sensitivity: f32,
+    mouse_sensitivity_x: f32,
+    mouse_sensitivity_y: f32,
+    /// Moving the mouse up looks down, like a flight stick.
+    invert_y: bool,
+    /// The camera as it was when the controller was created, for `reset`.
+    home: Camera,
 }
 
 impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(speed: f32, sensitivity: f32, camera: &Camera) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -121,40 +401,82 @@ impl CameraController {
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             scroll: 0.0,
+            pending_zoom: 0.0,
+            zoom_sensitivity: DEFAULT_ZOOM_SENSITIVITY,
+            min_distance: DEFAULT_MIN_ZOOM_DISTANCE,
+            max_distance: DEFAULT_MAX_ZOOM_DISTANCE,
             speed,
-            sensitivity,
+            mouse_sensitivity_x: sensitivity,
+            mouse_sensitivity_y: sensitivity,
+            invert_y: false,
+            home: camera.clone(),
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+    /// Puts `camera` back where it started and stops any movement in progress.
+    pub fn reset(&mut self, camera: &mut Camera) {
+        *camera = self.home.clone();
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+        self.pending_zoom = 0.0;
+    }
+
+    /// How far the camera moves per line scrolled.
+    pub fn set_zoom_sensitivity(&mut self, sensitivity: f32) {
+        self.zoom_sensitivity = sensitivity;
+    }
+
+    /// Keeps scrolling between `min` and `max` units from the orbit target.
+    pub fn set_zoom_limits(&mut self, min: f32, max: f32) {
+        self.min_distance = min.max(MIN_ORBIT_DISTANCE);
+        self.max_distance = max.max(self.min_distance);
+    }
+
+    /// Scales how far the camera turns per unit of mouse movement.
+    pub fn set_mouse_sensitivity(&mut self, x: f32, y: f32) {
+        self.mouse_sensitivity_x = x;
+        self.mouse_sensitivity_y = y;
+    }
+
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+    }
+
+    pub fn process_keyboard(&mut self, action: Action, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
             0.0
         };
-        println!("LEFT SHIFT! {:?}", key);
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
+        match action {
+            Action::MoveForward => {
                 self.amount_forward = amount;
                 true
             }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
+            Action::MoveBackward => {
                 self.amount_backward = amount;
                 true
             }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
+            Action::MoveLeft => {
                 self.amount_left = amount;
                 true
             }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
+            Action::MoveRight => {
                 self.amount_right = amount;
                 true
             }
-            VirtualKeyCode::Space => {
+            Action::MoveUp => {
                 self.amount_up = amount;
                 true
             }
-            VirtualKeyCode::LControl | VirtualKeyCode::LShift => {
+            Action::MoveDown => {
                 self.amount_down = amount;
                 true
             }
@@ -163,8 +485,9 @@ impl CameraController {
     }
 
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        let invert = if self.invert_y { -1.0 } else { 1.0 };
+        self.rotate_horizontal = mouse_dx as f32 * self.mouse_sensitivity_x;
+        self.rotate_vertical = mouse_dy as f32 * self.mouse_sensitivity_y * invert;
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
@@ -175,35 +498,49 @@ impl CameraController {
         };
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+    /// Moves `camera` by whatever input came in since the last call. Returns
+    /// whether it moved.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) -> bool {
         let dt = dt.as_secs_f32();
+        let before = (camera.position, camera.yaw, camera.pitch);
 
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        camera.pan(forward * (self.amount_forward - self.amount_backward) * self.speed * dt);
+        camera.pan(right * (self.amount_right - self.amount_left) * self.speed * dt);
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
         // changes when zooming. I've added this to make it easier
         // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward =
-            Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
+        self.pending_zoom -= self.scroll * self.zoom_sensitivity;
         self.scroll = 0.0;
+        if self.pending_zoom != 0.0 {
+            let step = self.pending_zoom * (1.0 - (-ZOOM_EASING * dt).exp());
+            let distance = (camera.distance + step).clamp(self.min_distance, self.max_distance);
+            if distance == camera.distance + step {
+                self.pending_zoom -= step;
+                if self.pending_zoom.abs() < 1e-3 {
+                    self.pending_zoom = 0.0;
+                }
+            } else {
+                // Hit a limit, don't keep pushing against it
+                self.pending_zoom = 0.0;
+            }
+            camera.zoom(distance - camera.distance);
+        }
 
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        camera.pan(Vector3::unit_y() * (self.amount_up - self.amount_down) * self.speed * dt);
 
-        // Rotate
-        // camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        // camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
-        camera.yaw += Rad(self.rotate_horizontal) * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * dt;
+        // Rotate around the target
+        camera.orbit(
+            Rad(self.rotate_horizontal) * dt,
+            Rad(-self.rotate_vertical) * dt,
+        );
 
         // If process_mouse isn't called every frame, these values
         // will not get set to zero, and the camera will rotate
@@ -211,11 +548,6 @@ impl CameraController {
         self.rotate_horizontal = 0.0;
         self.rotate_vertical = 0.0;
 
-        // Keep the camera's angle from going too high/low.
-        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = -Rad(SAFE_FRAC_PI_2);
-        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = Rad(SAFE_FRAC_PI_2);
-        }
+        (camera.position, camera.yaw, camera.pitch) != before
     }
 }
@@ -1,32 +1,252 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "parallel")]
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use cgmath::{vec2, vec3, InnerSpace, Matrix4, Point3, Transform, Vector2, Vector3};
+use anyhow::{bail, Result};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{vec2, vec3, InnerSpace, Matrix4, Point3, Transform, Vector2, Vector3, Vector4};
 use wgpu::util::DeviceExt;
 
-use crate::{ray::Ray, texture::Texture, Vertex, Vertex2, SAMPLE_COUNT};
+#[cfg(feature = "gltf")]
+mod gltf;
 
+#[cfg(feature = "compute")]
+use crate::compute::{ComputeParams, ComputeSolver};
+use crate::{
+    camera::CameraUniform,
+    outline::{Outline, OUTLINE_STENCIL_REF},
+    ray::Ray,
+    texture::{DepthConfig, ImageSource, Texture, TextureOptions},
+    Vertex, Vertex2,
+};
+
+/// The default simulation step, 120 Hz.
 pub const TIME_STEP: f32 = 1.0 / 120.0;
 pub const DT: f32 = 0.01;
+/// The default share of velocity each particle loses per step, see `Cloth::set_damping`.
 pub const DAMPING: f32 = 0.01;
+/// How far a constraint can stretch, relative to its rest length, before it tears.
+pub const DEFAULT_MAX_STRETCH_RATIO: f32 = 2.0;
+/// The share of horizontal velocity a particle resting on the floor loses each step.
+pub const FLOOR_FRICTION: f32 = 0.1;
+/// World units a particle moves per unit of mouse drag.
+pub const MOUSE_DRAG_SCALE: f32 = 0.01;
+/// How many grid cells around the grabbed particle a drag pulls along.
+pub const DEFAULT_DRAG_RADIUS: f32 = 3.0;
+/// The furthest a particle can move in one step, in world units. Generous
+/// enough not to matter until something goes wrong.
+pub const DEFAULT_MAX_VELOCITY: f32 = 1.0;
+pub const DEFAULT_GRAVITY: Vector3<f32> = Vector3::new(0.0, -0.8, 0.0);
+pub const DEFAULT_WIND: Vector3<f32> = Vector3::new(50.5, 0.0, 0.2);
 pub const DEFAULT_INSTANCE_BUFFER_COUNT: u64 = 1024;
 
 // pub const CONSTRAINT_ITERATIONS: usize = 30;
 // pub const CONSTRAINT_ITERATIONS: usize = 10;
+/// The default number of constraint passes per step, see `Cloth::set_constraint_iterations`.
 pub const CONSTRAINT_ITERATIONS: usize = 2;
 // pub const CONSTRAINT_ITERATIONS: usize = 5;
 // pub const CONSTRAINT_ITERATIONS: usize = 2;
 
-fn time_secs() -> f64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs_f64()
+/// The color format a headless cloth's pipelines are built for.
+pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// The most time one update will try to catch up on, so a long stall (a
+/// backgrounded tab, a breakpoint) doesn't queue up thousands of steps.
+pub const MAX_FRAME_TIME: f32 = 0.25;
+/// The most steps one update will run before dropping the rest.
+pub const MAX_SUBSTEPS: usize = 32;
+
+/// The frame time adaptive iterations aim for by default, see
+/// `Physics::set_adaptive`.
+pub const DEFAULT_FRAME_BUDGET: std::time::Duration = std::time::Duration::from_micros(16_667);
+/// How many frames in a row have to go over or under the budget before
+/// adaptive iterations drop or add a pass.
+const ADAPTIVE_SLOW_FRAMES: u32 = 30;
+const ADAPTIVE_FAST_FRAMES: u32 = 120;
+/// Below this, two particles are treated as on top of each other and their
+/// constraint has no direction to push them apart in.
+const MIN_CONSTRAINT_DISTANCE: f32 = 1e-6;
+/// The share of the particles that can go NaN in one step before safe mode
+/// gives up on patching them and resets the whole sheet.
+const MAX_CORRUPT_PARTICLES: f32 = 0.1;
+/// The slowest and fastest `Physics::set_time_scale` goes.
+pub const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+pub const MAX_TIME_SCALE: f32 = 4.0;
+
+/// Adds `frame_time`, sped up or slowed down by `time_scale`, to the
+/// accumulator and returns how many fixed steps to run, never more than
+/// `MAX_SUBSTEPS`.
+fn take_substeps(accumulator: &mut f32, frame_time: f32, time_step: f32, time_scale: f32) -> usize {
+    *accumulator += frame_time.min(MAX_FRAME_TIME) * time_scale;
+    let mut steps = (*accumulator / time_step) as usize;
+    if steps > MAX_SUBSTEPS {
+        log::warn!(
+            "Simulation fell {} steps behind, dropping {}",
+            steps,
+            steps - MAX_SUBSTEPS
+        );
+        steps = MAX_SUBSTEPS;
+        *accumulator = 0.0;
+    } else {
+        *accumulator -= steps as f32 * time_step;
+    }
+    steps
 }
 
 pub struct Physics {
     accumulator: f32,
-    pub cloth: Cloth,
-    current_time: f64,
+    time_step: f32,
+    cloth: Arc<Mutex<Cloth>>,
+    current_time: instant::Instant,
+    /// Stops `update` from advancing the cloth, see `set_paused`.
+    paused: bool,
+    /// How many steps the last `update` caught up on, see `substeps`.
+    substeps: usize,
+    /// Simulated seconds per real second, see `set_time_scale`.
+    time_scale: f32,
+    adaptive: Option<AdaptiveIterations>,
+    #[cfg(not(target_arch = "wasm32"))]
+    simulation: Option<SimulationThread>,
+}
+
+/// Steps the cloth at the fixed timestep on its own thread so a heavy grid
+/// doesn't hold up rendering. The lock is only held for one step at a time,
+/// and the positions and normals after the last step are handed over in
+/// `snapshot` so the render thread doesn't rebuild them from the particles.
+#[cfg(not(target_arch = "wasm32"))]
+struct SimulationThread {
+    /// The bits of the `f32` step, shared so the tick rate can change while
+    /// the thread runs.
+    time_step: Arc<std::sync::atomic::AtomicU32>,
+    /// The bits of the `f32` time scale, like `time_step`.
+    time_scale: Arc<std::sync::atomic::AtomicU32>,
+    paused: Arc<std::sync::atomic::AtomicBool>,
+    stop: Arc<std::sync::atomic::AtomicBool>,
+    dirty: Arc<std::sync::atomic::AtomicBool>,
+    /// Steps taken since the render thread last uploaded the cloth.
+    substeps: Arc<std::sync::atomic::AtomicUsize>,
+    /// The latest published snapshot. The thread fills a buffer of its own
+    /// and swaps it in here, getting the previous one back to reuse.
+    snapshot: Arc<Mutex<ClothSnapshot>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SimulationThread {
+    fn spawn(cloth: Arc<Mutex<Cloth>>, time_step: f32, time_scale: f32) -> Self {
+        use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+        let time_step = Arc::new(AtomicU32::new(time_step.to_bits()));
+        let time_scale = Arc::new(AtomicU32::new(time_scale.to_bits()));
+        let paused = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let dirty = Arc::new(AtomicBool::new(false));
+        let substeps = Arc::new(AtomicUsize::new(0));
+        let snapshot = Arc::new(Mutex::new(ClothSnapshot::default()));
+
+        let handle = {
+            let time_step = time_step.clone();
+            let time_scale = time_scale.clone();
+            let paused = paused.clone();
+            let stop = stop.clone();
+            let dirty = dirty.clone();
+            let substeps = substeps.clone();
+            let snapshot = snapshot.clone();
+            std::thread::Builder::new()
+                .name("simulation".into())
+                .spawn(move || {
+                    let mut current_time = instant::Instant::now();
+                    let mut accumulator = 0.0;
+                    let mut back = ClothSnapshot::default();
+                    while !stop.load(Ordering::Relaxed) {
+                        let step = f32::from_bits(time_step.load(Ordering::Relaxed));
+                        let scale = f32::from_bits(time_scale.load(Ordering::Relaxed));
+                        let new_time = instant::Instant::now();
+                        if paused.load(Ordering::Relaxed) {
+                            // Drop the paused time instead of catching up on it
+                            current_time = new_time;
+                            accumulator = 0.0;
+                            std::thread::sleep(std::time::Duration::from_secs_f32(step));
+                            continue;
+                        }
+                        let steps = take_substeps(
+                            &mut accumulator,
+                            (new_time - current_time).as_secs_f32(),
+                            step,
+                            scale,
+                        );
+                        current_time = new_time;
+
+                        for i in 0..steps {
+                            // Let go between steps so drawing and picking
+                            // never wait on more than one
+                            let mut cloth = cloth.lock().unwrap();
+                            cloth.update(step);
+                            if i + 1 == steps {
+                                cloth.update_normals();
+                                Cloth::fill_vertices(
+                                    &cloth.particles,
+                                    &mut back.vertices,
+                                    &mut back.normals,
+                                );
+                            }
+                        }
+                        if steps > 0 {
+                            std::mem::swap(&mut *snapshot.lock().unwrap(), &mut back);
+                            substeps.fetch_add(steps, Ordering::Relaxed);
+                            dirty.store(true, Ordering::Release);
+                        }
+
+                        // Until the next step is due, in real time. Dropping
+                        // steps can leave the accumulator past a whole step.
+                        std::thread::sleep(std::time::Duration::from_secs_f32(
+                            ((step - accumulator) / scale).max(0.0),
+                        ));
+                    }
+                })
+                .expect("Failed to spawn simulation thread")
+        };
+
+        Self {
+            time_step,
+            time_scale,
+            paused,
+            stop,
+            dirty,
+            substeps,
+            snapshot,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// Positions and normals as of some step, in the layout `fill_vertices`
+/// writes them.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct ClothSnapshot {
+    vertices: Vec<Vertex>,
+    normals: Vec<Vertex>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for SimulationThread {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Tracks whether the simulation keeps up with the frame budget, see
+/// `Physics::set_adaptive`.
+struct AdaptiveIterations {
+    budget: std::time::Duration,
+    /// The iteration count adapting started from, never gone above.
+    max_iterations: usize,
+    slow_frames: u32,
+    fast_frames: u32,
 }
 
 impl Physics {
@@ -34,58 +254,594 @@ impl Physics {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         format: wgpu::TextureFormat,
+        sample_count: u32,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        cloth: ClothBuilder,
     ) -> Self {
+        let cloth = Arc::new(Mutex::new(cloth.build(
+            device,
+            queue,
+            format,
+            sample_count,
+            camera_bind_group_layout,
+        )));
+
         Self {
-            current_time: time_secs(),
+            current_time: instant::Instant::now(),
             accumulator: 0.0,
-            cloth: Cloth::new(
-                device,
-                queue,
-                format,
-                camera_bind_group_layout,
-                // more cloth-y toilet paper
-                14.0,
-                10.0,
-                45 * 3,
-                55 * 3,
-                // most accurate toilet paper
-                // 10.0,
-                // 14.0,
-                // 22,
-                // 26,
-                // long 16:9-like cloth
-                // 10.0,
-                // 14.0,
-                // 45,
-                // 55,
-            ),
-        }
-    }
-
-    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) {
+            time_step: TIME_STEP,
+            paused: false,
+            substeps: 0,
+            time_scale: 1.0,
+            adaptive: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            simulation: Some(SimulationThread::spawn(cloth.clone(), TIME_STEP, 1.0)),
+            cloth,
+        }
+    }
+
+    pub fn tick_rate(&self) -> f32 {
+        1.0 / self.time_step
+    }
+
+    /// Sets how many simulation steps run per second, 120 by default.
+    ///
+    /// Constraints are only relaxed a fixed number of times per step, so
+    /// this also changes how stiff the cloth feels: a higher rate makes it
+    /// stiffer and a lower one stretchier, unless the iteration count is
+    /// scaled to match.
+    pub fn set_tick_rate(&mut self, hz: f32) {
+        self.time_step = 1.0 / hz.max(1.0);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(simulation) = self.simulation.as_ref() {
+            simulation.time_step.store(
+                self.time_step.to_bits(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+        }
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Sets how many seconds are simulated per real second, between
+    /// `MIN_TIME_SCALE` and `MAX_TIME_SCALE`: below 1 the cloth drapes in
+    /// slow motion, above 1 it's sped up. The step size stays the same, only
+    /// how many steps run per frame changes, so the cloth behaves the same
+    /// at any speed.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(simulation) = self.simulation.as_ref() {
+            simulation.time_scale.store(
+                self.time_scale.to_bits(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+        }
+    }
+
+    /// What the last `update` or `step_fixed` returned or was asked for.
+    pub fn substeps(&self) -> usize {
+        self.substeps
+    }
+
+    /// Trades stiffness for speed under load: while frames need more steps
+    /// than fit in the frame budget, constraint passes are dropped one at a
+    /// time down to a single pass, and added back when there's headroom, up
+    /// to the count set when adapting started. Turning it off restores that
+    /// count.
+    pub fn set_adaptive(&mut self, enabled: bool) {
+        match (enabled, self.adaptive.take()) {
+            (true, Some(adaptive)) => self.adaptive = Some(adaptive),
+            (true, None) => {
+                let max_iterations = self.cloth().constraint_iterations();
+                self.adaptive = Some(AdaptiveIterations {
+                    budget: DEFAULT_FRAME_BUDGET,
+                    max_iterations,
+                    slow_frames: 0,
+                    fast_frames: 0,
+                })
+            }
+            (false, Some(adaptive)) => self
+                .cloth()
+                .set_constraint_iterations(adaptive.max_iterations),
+            (false, None) => (),
+        }
+    }
+
+    pub fn adaptive(&self) -> bool {
+        self.adaptive.is_some()
+    }
+
+    /// Sets the frame time adaptive iterations aim for, `DEFAULT_FRAME_BUDGET`
+    /// (60 fps) by default.
+    pub fn set_frame_budget(&mut self, budget: std::time::Duration) {
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            adaptive.budget = budget;
+        }
+    }
+
+    /// Counts `steps` against the frame budget and changes the iteration
+    /// count once enough frames in a row were over or under it.
+    fn adapt(&mut self, steps: usize) {
+        if self.paused {
+            return;
+        }
+        let time_step = self.time_step;
+        let time_scale = self.time_scale;
+        let adaptive = match self.adaptive.as_mut() {
+            Some(adaptive) => adaptive,
+            None => return,
+        };
+        let budget_steps = (adaptive.budget.as_secs_f32() * time_scale / time_step).ceil() as usize;
+        if steps > budget_steps {
+            adaptive.slow_frames += 1;
+            adaptive.fast_frames = 0;
+        } else {
+            adaptive.fast_frames += 1;
+            adaptive.slow_frames = 0;
+        }
+
+        let fewer = if adaptive.slow_frames >= ADAPTIVE_SLOW_FRAMES {
+            adaptive.slow_frames = 0;
+            true
+        } else if adaptive.fast_frames >= ADAPTIVE_FAST_FRAMES {
+            adaptive.fast_frames = 0;
+            false
+        } else {
+            return;
+        };
+        let max_iterations = adaptive.max_iterations;
+
+        let mut cloth = self.cloth();
+        let iterations = cloth.constraint_iterations();
+        let adapted = if fewer {
+            iterations.saturating_sub(1).max(1)
+        } else {
+            (iterations + 1).min(max_iterations)
+        };
+        if adapted != iterations {
+            log::debug!(
+                "Adapting constraint iterations {} -> {}",
+                iterations,
+                adapted
+            );
+            cloth.set_constraint_iterations(adapted);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Freezes the cloth where it is until unpaused. Time spent paused isn't
+    /// made up for afterwards; `step_once` still advances it by hand.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.accumulator = 0.0;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(simulation) = self.simulation.as_ref() {
+            simulation
+                .paused
+                .store(paused, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Advances exactly one step and uploads it, e.g. to walk through the
+    /// constraint solver frame by frame while paused. This always steps on
+    /// the CPU, starting from wherever the GPU solver left the cloth.
+    pub fn step_once(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        #[cfg(not(feature = "compute"))]
+        let _ = device;
+        {
+            let mut cloth = self.cloth();
+            #[cfg(feature = "compute")]
+            cloth.sync_from_gpu(device, queue);
+            cloth.update(self.time_step);
+            cloth.update_normals();
+        }
+        self.update_wgpu(queue);
+    }
+
+    /// Builds the default cloth for stepping without a window or surface, e.g.
+    /// in tests. It renders to `HEADLESS_FORMAT` if drawn at all, and steps
+    /// only when `update` or `step_fixed` is called so runs are reproducible.
+    pub fn new_headless(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let camera_bind_group_layout = CameraUniform::create_bind_group_layout(device);
+        let cloth =
+            ClothBuilder::new().build(device, queue, HEADLESS_FORMAT, 1, &camera_bind_group_layout);
+
+        Self {
+            current_time: instant::Instant::now(),
+            accumulator: 0.0,
+            time_step: TIME_STEP,
+            paused: false,
+            substeps: 0,
+            time_scale: 1.0,
+            adaptive: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            simulation: None,
+            cloth: Arc::new(Mutex::new(cloth)),
+        }
+    }
+
+    /// Restarts the simulation from the initial hanging sheet.
+    pub fn reset(&mut self, queue: &wgpu::Queue) {
+        self.accumulator = 0.0;
+        self.cloth().reset();
+        self.update_wgpu(queue);
+    }
+
+    /// Advances exactly `steps` fixed steps without reading the clock, so the
+    /// same starting state and step count always give the same result. Call
+    /// `update_wgpu` afterwards to upload it.
+    ///
+    /// This stops the simulation thread, which would otherwise keep stepping
+    /// the cloth in real time; `update` runs on the calling thread afterwards
+    /// until `start_simulation_thread` is called.
+    pub fn step_fixed(&mut self, steps: usize) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.simulation = None;
+        }
+        self.accumulator = 0.0;
+        self.substeps = steps;
+
+        let mut cloth = self.cloth();
+        for _ in 0..steps {
+            cloth.update(self.time_step);
+        }
+        cloth.update_normals();
+    }
+
+    /// Goes back to stepping in real time on a thread of its own after
+    /// `step_fixed` or `set_gpu_solver` stopped it. Does nothing if it's
+    /// already running, or while the GPU solver is on.
+    pub fn start_simulation_thread(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            #[cfg(feature = "compute")]
+            if self.cloth().gpu_solver_enabled() {
+                return;
+            }
+            if self.simulation.is_none() {
+                let simulation =
+                    SimulationThread::spawn(self.cloth.clone(), self.time_step, self.time_scale);
+                simulation
+                    .paused
+                    .store(self.paused, std::sync::atomic::Ordering::Relaxed);
+                self.simulation = Some(simulation);
+            }
+        }
+    }
+
+    /// Locks the cloth, blocking while the simulation thread is stepping it.
+    pub fn cloth(&self) -> MutexGuard<'_, Cloth> {
+        self.cloth.lock().unwrap()
+    }
+
+    /// Catches the cloth up on `dt` of simulated time and uploads it,
+    /// returning how many fixed steps that took. With the simulation thread
+    /// running, this only uploads what it stepped since the last call.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) -> usize {
+        let steps = self.advance(queue, dt);
+        self.adapt(steps);
+        steps
+    }
+
+    fn advance(&mut self, queue: &wgpu::Queue, dt: std::time::Duration) -> usize {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(simulation) = self.simulation.as_ref() {
+            if simulation
+                .dirty
+                .swap(false, std::sync::atomic::Ordering::Acquire)
+            {
+                self.substeps = simulation
+                    .substeps
+                    .swap(0, std::sync::atomic::Ordering::Relaxed);
+                let mut snapshot = simulation.snapshot.lock().unwrap();
+                self.cloth
+                    .lock()
+                    .unwrap()
+                    .upload_snapshot(queue, &mut snapshot);
+            } else {
+                self.substeps = 0;
+            }
+            return self.substeps;
+        }
+
+        self.substeps = 0;
+        if self.paused {
+            return 0;
+        }
+
         // let new_time = time_secs();
         // let frame_time = new_time - self.current_time;
         // self.current_time = new_time;
         let frame_time = dt.as_secs_f64();
 
-        let mut updated = false;
-
-        self.accumulator += frame_time as f32;
-        while self.accumulator >= TIME_STEP {
-            self.accumulator -= TIME_STEP;
-            self.cloth.update(TIME_STEP);
-            updated = true;
+        let steps = take_substeps(
+            &mut self.accumulator,
+            frame_time as f32,
+            self.time_step,
+            self.time_scale,
+        );
+        self.substeps = steps;
+        for _ in 0..steps {
+            self.cloth().update(self.time_step);
         }
 
-        if updated {
-            self.cloth.update_normals();
+        if steps > 0 {
+            self.cloth().update_normals();
             self.update_wgpu(&queue);
         }
+        steps
+    }
+
+    /// Switches between stepping on the GPU and the CPU, see
+    /// `Cloth::enable_gpu_solver`. The GPU solver runs on the render thread,
+    /// so turning it on stops the simulation thread and turning it off starts
+    /// it again.
+    #[cfg(feature = "compute")]
+    pub fn set_gpu_solver(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, enabled: bool) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.simulation = None;
+        }
+        self.accumulator = 0.0;
+
+        if enabled {
+            self.cloth().enable_gpu_solver(device);
+        } else {
+            self.cloth().disable_gpu_solver(device, queue);
+            self.start_simulation_thread();
+        }
+    }
+
+    /// Like `update`, but steps on the GPU once `set_gpu_solver` turned it on.
+    #[cfg(feature = "compute")]
+    pub fn update_gpu(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: std::time::Duration,
+    ) -> usize {
+        if !self.cloth().gpu_solver_enabled() {
+            return self.update(queue, dt);
+        }
+        self.substeps = 0;
+        if self.paused {
+            return 0;
+        }
+
+        let steps = take_substeps(
+            &mut self.accumulator,
+            dt.as_secs_f32(),
+            self.time_step,
+            self.time_scale,
+        );
+        self.substeps = steps;
+        if steps > 0 {
+            self.cloth().step_gpu(device, queue, steps, self.time_step);
+        }
+        self.adapt(steps);
+        steps
     }
 
     pub fn update_wgpu(&mut self, queue: &wgpu::Queue) {
-        self.cloth.update_wgpu(queue);
+        self.cloth().update_wgpu(queue);
+    }
+}
+
+/// How the wind blowing on the cloth changes over time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WindModel {
+    Constant(Vector3<f32>),
+    /// Oscillates as `base + amplitude * sin(2π * frequency * t)`, with `t`
+    /// the simulated time in seconds.
+    Gust {
+        base: Vector3<f32>,
+        amplitude: Vector3<f32>,
+        frequency: f32,
+    },
+}
+
+impl WindModel {
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        match *self {
+            WindModel::Constant(wind) => wind,
+            WindModel::Gust {
+                base,
+                amplitude,
+                frequency,
+            } => base + amplitude * (2.0 * std::f32::consts::PI * frequency * t).sin(),
+        }
+    }
+}
+
+/// Which particles are pinned in place when the cloth is built or reset.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PinLayout {
+    /// A few particles at either end of the top row, like paper hanging
+    /// from two clips.
+    #[default]
+    TopCorners,
+    /// The whole top row, like a curtain.
+    TopEdge,
+    /// A few particles at every corner, for a trampoline-like sheet.
+    FourCorners,
+    /// Nothing, the cloth falls freely.
+    None,
+}
+
+/// Describes a `Cloth` before its GPU resources are created.
+#[derive(Clone, Debug)]
+pub struct ClothBuilder {
+    width: f32,
+    height: f32,
+    particles_x: usize,
+    particles_y: usize,
+    constraint_iterations: usize,
+    gravity: Vector3<f32>,
+    wind: Vector3<f32>,
+    image: Option<ImageSource>,
+    texture_options: TextureOptions,
+    pin_layout: PinLayout,
+}
+
+impl Default for ClothBuilder {
+    fn default() -> Self {
+        Self {
+            width: 14.0,
+            height: 10.0,
+            particles_x: 45 * 3,
+            particles_y: 55 * 3,
+            constraint_iterations: CONSTRAINT_ITERATIONS,
+            gravity: DEFAULT_GRAVITY,
+            wind: DEFAULT_WIND,
+            image: None,
+            texture_options: TextureOptions::default(),
+            pin_layout: PinLayout::default(),
+        }
+    }
+}
+
+impl ClothBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Width of the sheet in world units.
+    pub fn width(mut self, width: f32) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Height of the sheet in world units.
+    pub fn height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Number of particles across, at least 3.
+    pub fn particles_x(mut self, particles_x: usize) -> Self {
+        self.particles_x = particles_x.max(3);
+        self
+    }
+
+    /// Number of particles down, at least 3.
+    pub fn particles_y(mut self, particles_y: usize) -> Self {
+        self.particles_y = particles_y.max(3);
+        self
+    }
+
+    pub fn constraint_iterations(mut self, constraint_iterations: usize) -> Self {
+        self.constraint_iterations = constraint_iterations.max(1);
+        self
+    }
+
+    pub fn gravity(mut self, gravity: Vector3<f32>) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn wind(mut self, wind: Vector3<f32>) -> Self {
+        self.wind = wind;
+        self
+    }
+
+    /// The image printed on the cloth, the embedded tweet by default.
+    pub fn image(mut self, image: ImageSource) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    /// How the image is filtered and whether it gets mip levels. Linear
+    /// filtering with mipmaps by default, `TextureOptions::nearest()` keeps
+    /// pixel art crisp. Tiling always repeats, whatever the address mode.
+    pub fn texture_options(mut self, texture_options: TextureOptions) -> Self {
+        self.texture_options = texture_options;
+        self
+    }
+
+    /// Lowers the anisotropy set by `texture_options` to at most `max`, e.g.
+    /// where the adapter can't filter anisotropically.
+    pub fn limit_anisotropy(mut self, max: u16) -> Self {
+        self.texture_options.anisotropy = self.texture_options.anisotropy.min(max.max(1));
+        self
+    }
+
+    /// Which particles hold the cloth up, `PinLayout::TopCorners` by default.
+    pub fn pin_layout(mut self, pin_layout: PinLayout) -> Self {
+        self.pin_layout = pin_layout;
+        self
+    }
+
+    pub fn build(
+        self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Cloth {
+        Cloth::new(
+            &self,
+            device,
+            queue,
+            format,
+            sample_count,
+            camera_bind_group_layout,
+        )
+    }
+
+    /// Builds only the CPU side of the cloth, without any GPU resources, e.g.
+    /// to step it in tests. The image and texture options are ignored.
+    pub fn build_simulation(&self) -> ClothSimulation {
+        let (particles, constraints) = ClothSimulation::create_grid(
+            self.width,
+            self.height,
+            self.particles_x,
+            self.particles_y,
+            self.pin_layout,
+        );
+        #[cfg(feature = "parallel")]
+        let (constraints, constraint_colors) = color_constraints(constraints, particles.len());
+
+        let mut sim = ClothSimulation {
+            particles,
+            constraints,
+            #[cfg(feature = "parallel")]
+            constraint_colors,
+            #[cfg(feature = "parallel")]
+            corrections: vec![],
+            edges: EdgeSet::new(self.particles_x, self.particles_y),
+            tear_threshold: DEFAULT_MAX_STRETCH_RATIO,
+            gravity: self.gravity,
+            wind: WindModel::Constant(self.wind),
+            wind_front_faces_only: false,
+            sim_time: 0.0,
+            damping: DAMPING,
+            max_velocity: DEFAULT_MAX_VELOCITY,
+            colliders: vec![],
+            floor_y: None,
+            safe_mode: cfg!(debug_assertions),
+            constraint_iterations: self.constraint_iterations,
+            structural_stiffness: 1.0,
+            bending_stiffness: 1.0,
+            view_right: Vector3::unit_x(),
+            view_up: Vector3::unit_y(),
+            num_particles_width: self.particles_x,
+            num_particles_height: self.particles_y,
+            width: self.width,
+            height: self.height,
+            indices_dirty: false,
+            torn: Vec::new(),
+            pin_layout: self.pin_layout,
+        };
+        sim.update_normals();
+        sim
     }
 }
 
@@ -97,6 +853,8 @@ pub struct Particle {
     pub tex_coords: Vector2<f32>,
     pub accumulated_normal: Vector3<f32>,
     pub is_movable: bool,
+    /// One over the particle's mass, heavier particles give way less to constraints.
+    pub inv_mass: f32,
 }
 
 impl Default for Particle {
@@ -108,6 +866,7 @@ impl Default for Particle {
             tex_coords: (0.0, 0.0).into(),
             accumulated_normal: (0.0, 0.0, 0.0).into(),
             is_movable: true,
+            inv_mass: 1.0,
         }
     }
 }
@@ -127,6 +886,15 @@ impl Particle {
         }
     }
 
+    /// Pinned particles act as if they had infinite mass.
+    pub fn effective_inv_mass(&self) -> f32 {
+        if self.is_movable {
+            self.inv_mass
+        } else {
+            0.0
+        }
+    }
+
     pub fn make_unmovable(&mut self) {
         self.is_movable = false;
     }
@@ -135,102 +903,571 @@ impl Particle {
         self.acceleration += dir;
     }
 
-    pub fn time_step(&mut self, timestep: f32) {
+    pub fn time_step(&mut self, timestep: f32, damping: f32, max_velocity: f32) {
         if self.is_movable {
             let temp = self.position;
-            self.position = self.position
-                + (self.position - self.old_position) * (1.0 - DAMPING)
-                + self.acceleration * timestep;
+            let mut velocity = self.position - self.old_position;
+            let speed = velocity.magnitude();
+            if speed > max_velocity {
+                velocity *= max_velocity / speed;
+            }
+            self.position =
+                self.position + velocity * (1.0 - damping) + self.acceleration * timestep;
             self.old_position = temp;
             self.acceleration = (0.0, 0.0, 0.0).into();
         }
     }
 }
 
+/// A snapshot of where every particle is and which links have torn, for
+/// saving and restoring a drape.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClothState {
+    pub num_particles_width: usize,
+    pub num_particles_height: usize,
+    pub positions: Vec<[f32; 3]>,
+    pub old_positions: Vec<[f32; 3]>,
+    pub is_movable: Vec<bool>,
+    pub inv_mass: Vec<f32>,
+    /// The particle pairs whose constraint tore.
+    pub torn: Vec<[usize; 2]>,
+}
+
+/// A ball the cloth can drape over.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vector3<f32>, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// Pushes a particle inside the sphere out to its surface. The previous
+    /// position moves by the same amount so the push doesn't add velocity to
+    /// the Verlet integration.
+    pub fn collide(&self, particle: &mut Particle) {
+        if !particle.is_movable {
+            return;
+        }
+        let offset = particle.position - self.center;
+        let distance = offset.magnitude();
+        if distance >= self.radius || distance == 0.0 {
+            return;
+        }
+        let correction = offset * (self.radius / distance - 1.0);
+        particle.position += correction;
+        particle.old_position += correction;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConstraintKind {
+    /// Holds immediate and diagonal neighbors together, keeping the sheet from stretching.
+    Structural,
+    /// Links particles two apart, resisting folds and creases.
+    Bending,
+}
+
 pub struct Constraint {
     pub p1: usize,
     pub p2: usize,
     pub rest_distance: f32,
+    pub max_stretch_ratio: f32,
+    pub kind: ConstraintKind,
+    /// How much of the correction is applied per pass, from 0 to 1.
+    pub stiffness: f32,
 }
 
 impl Constraint {
-    pub fn new(p1: usize, p2: usize, rest_distance: f32) -> Self {
+    pub fn new(p1: usize, p2: usize, rest_distance: f32, kind: ConstraintKind) -> Self {
         Self {
             p1,
             p2,
             rest_distance,
+            max_stretch_ratio: DEFAULT_MAX_STRETCH_RATIO,
+            kind,
+            stiffness: 1.0,
         }
     }
 
+    pub fn is_torn(&self, particles: &[Particle]) -> bool {
+        let current_distance =
+            (particles[self.p2].position - particles[self.p1].position).magnitude();
+        current_distance / self.rest_distance > self.max_stretch_ratio
+    }
+
     pub fn satisfy(&self, particles: &mut [Particle]) {
+        let (offset_p1, offset_p2) = self.correction(particles);
+        particles[self.p1].offset_pos(offset_p1);
+        particles[self.p2].offset_pos(offset_p2);
+    }
+
+    /// How far `satisfy` would move `p1` and `p2`, without moving them.
+    pub fn correction(&self, particles: &[Particle]) -> (Vector3<f32>, Vector3<f32>) {
         let p1_to_p2 = particles[self.p2].position - particles[self.p1].position;
         let current_distance = p1_to_p2.magnitude();
-        let correction_half = p1_to_p2 * (1.0 - self.rest_distance / current_distance) * 0.5;
-        particles[self.p1].offset_pos(correction_half);
-        particles[self.p2].offset_pos(-correction_half);
+        let inv_mass_p1 = particles[self.p1].effective_inv_mass();
+        let inv_mass_p2 = particles[self.p2].effective_inv_mass();
+        let total_inv_mass = inv_mass_p1 + inv_mass_p2;
+        // Dividing by a zero distance would turn both particles into NaN,
+        // which the other constraints then spread over the whole sheet. Leave
+        // them be, their other neighbors pull them apart again
+        if total_inv_mass == 0.0 || current_distance < MIN_CONSTRAINT_DISTANCE {
+            return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        }
+        let correction = p1_to_p2 * (1.0 - self.rest_distance / current_distance) * self.stiffness;
+        (
+            correction * (inv_mass_p1 / total_inv_mass),
+            -correction * (inv_mass_p2 / total_inv_mass),
+        )
     }
 }
 
-pub struct Cloth {
-    pos: Vector3<f32>,
-    old_pos: Vector3<f32>,
-    acceleration: Vector3<f32>,
-    particles: Vec<Particle>,
+/// Sorts `constraints` so that no two in the same returned range share a
+/// particle, letting each range be solved in parallel. Greedy, so it may use
+/// a few more colors than strictly needed; a grid ends up with about a dozen.
+#[cfg(feature = "parallel")]
+fn color_constraints(
     constraints: Vec<Constraint>,
+    num_particles: usize,
+) -> (Vec<Constraint>, Vec<Range<usize>>) {
+    // Bit `c` of `used[p]` is set once particle `p` is in a color `c`
+    // constraint. Anything past 64 colors shares the last one, which only
+    // makes that color's pass a little less exact.
+    let mut used = vec![0u64; num_particles];
+    let mut colored: Vec<(u32, Constraint)> = constraints
+        .into_iter()
+        .map(|constraint| {
+            let taken = used[constraint.p1] | used[constraint.p2];
+            let color = (!taken).trailing_zeros().min(63);
+            used[constraint.p1] |= 1 << color;
+            used[constraint.p2] |= 1 << color;
+            (color, constraint)
+        })
+        .collect();
+    colored.sort_by_key(|(color, _)| *color);
+
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for (i, (color, _)) in colored.iter().enumerate() {
+        match ranges.last_mut() {
+            Some(range) if colored[range.start].0 == *color => range.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    (
+        colored
+            .into_iter()
+            .map(|(_, constraint)| constraint)
+            .collect(),
+        ranges,
+    )
+}
+
+/// The kinds of grid edge that border a triangle, see `EdgeSet`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Edge {
+    /// `(x, y)` to `(x + 1, y)`
+    Right = 0,
+    /// `(x, y)` to `(x, y + 1)`
+    Down = 1,
+    /// `(x + 1, y)` to `(x, y + 1)`, shared by both triangles of a square
+    Diagonal = 2,
+}
 
+/// One bit per triangle edge in the grid, cleared once the constraint along
+/// that edge tears. Edges are keyed by the particle they start from.
+struct EdgeSet {
+    bits: Vec<u64>,
     num_particles_width: usize,
-    num_particles_height: usize,
+}
 
-    pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    vertex_normal_buffer: wgpu::Buffer,
-    tex_coord_buffer: wgpu::Buffer,
-    diffuse_bind_group: wgpu::BindGroup,
-    texture: Texture,
+impl EdgeSet {
+    fn new(num_particles_width: usize, num_particles_height: usize) -> Self {
+        let len = num_particles_width * num_particles_height * 3;
+        Self {
+            bits: vec![u64::MAX; len.div_ceil(64)],
+            num_particles_width,
+        }
+    }
 
-    vertices: Vec<Vertex>,
-    tex_coord: Vec<Vertex2>,
-    normals: Vec<Vertex>,
+    fn index(&self, x: usize, y: usize, edge: Edge) -> usize {
+        (y * self.num_particles_width + x) * 3 + edge as usize
+    }
+
+    fn is_alive(&self, x: usize, y: usize, edge: Edge) -> bool {
+        let i = self.index(x, y, edge);
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// Marks the edge between particles `p1` and `p2` as torn, if it is one
+    /// of the edges triangles are built from.
+    fn tear(&mut self, p1: usize, p2: usize) {
+        let w = self.num_particles_width;
+        let (a, b) = (p1.min(p2), p1.max(p2));
+        let (ax, ay) = (a % w, a / w);
+        let (bx, by) = (b % w, b / w);
+        let (x, y, edge) = match (bx as isize - ax as isize, by - ay) {
+            (1, 0) => (ax, ay, Edge::Right),
+            (0, 1) => (ax, ay, Edge::Down),
+            (-1, 1) => (bx, ay, Edge::Diagonal),
+            _ => return,
+        };
+        let i = self.index(x, y, edge);
+        self.bits[i / 64] &= !(1 << (i % 64));
+    }
+
+    /// Whether both triangles of the square at `(x, y)` are still whole.
+    fn triangles_alive(&self, x: usize, y: usize) -> [bool; 2] {
+        let diagonal = self.is_alive(x, y, Edge::Diagonal);
+        [
+            diagonal && self.is_alive(x, y, Edge::Right) && self.is_alive(x, y, Edge::Down),
+            diagonal && self.is_alive(x + 1, y, Edge::Down) && self.is_alive(x, y + 1, Edge::Right),
+        ]
+    }
 }
 
-impl Cloth {
-    const INDICES: &[u16] = &[0, 2, 1];
-    const NORMAL_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![1=>Float32x3];
-    const TEX_COORD_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![2=>Float32x2];
-    fn normal_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::NORMAL_ATTRIBUTES,
+/// How an image is fitted onto a surface with a different aspect ratio.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FitMode {
+    /// Show the whole image centered, leaving bars where it doesn't reach.
+    Contain,
+    /// Fill the whole surface, cropping whatever overflows.
+    Cover,
+    /// Stretch the image over the surface, ignoring its aspect ratio.
+    Stretch,
+}
+
+impl FitMode {
+    /// Maps normalized surface coordinates `(u, v)` to image coordinates.
+    pub fn tex_coords(
+        &self,
+        u: f32,
+        v: f32,
+        surface_aspect: f32,
+        image_aspect: f32,
+    ) -> Vector2<f32> {
+        let surface_is_wider = surface_aspect > image_aspect;
+        match self {
+            FitMode::Stretch => vec2(u, v),
+            FitMode::Contain if surface_is_wider => {
+                vec2((u - 0.5) * surface_aspect / image_aspect + 0.5, v)
+            }
+            FitMode::Contain => vec2(u, (v - 0.5) * image_aspect / surface_aspect + 0.5),
+            FitMode::Cover if surface_is_wider => {
+                vec2(u, (v - 0.5) * image_aspect / surface_aspect + 0.5)
+            }
+            FitMode::Cover => vec2((u - 0.5) * surface_aspect / image_aspect + 0.5, v),
         }
     }
-    fn tex_coord_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex2>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::TEX_COORD_ATTRIBUTES,
+}
+
+/// How the texture is laid out across the cloth.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UvMapping {
+    /// Fit a single copy of the image onto the sheet.
+    Fit(FitMode),
+    /// Repeat the image `x` by `y` times across the sheet.
+    Tile { x: u32, y: u32 },
+}
+
+impl Default for UvMapping {
+    fn default() -> Self {
+        Self::Fit(FitMode::Stretch)
+    }
+}
+
+impl UvMapping {
+    /// Maps normalized grid coordinates `(u, v)` to texture coordinates.
+    pub fn tex_coords(
+        &self,
+        u: f32,
+        v: f32,
+        cloth_aspect: f32,
+        texture_aspect: f32,
+    ) -> Vector2<f32> {
+        match *self {
+            UvMapping::Fit(fit) => fit.tex_coords(u, v, cloth_aspect, texture_aspect),
+            UvMapping::Tile { x, y } => vec2(u * x as f32, v * y as f32),
         }
     }
 
-    pub fn set_moveable(&mut self, x: usize, y: usize, moveable: bool) {
-        let i0 = self.get_particle_idx(x + 1, y);
-        let i1 = self.get_particle_idx(x, y);
-        let i2 = self.get_particle_idx(x, y + 1);
-        self.particles[i0].is_movable = moveable;
-        self.particles[i1].is_movable = moveable;
-        self.particles[i2].is_movable = moveable;
+    /// `options` with the address mode tiling needs.
+    fn texture_options(&self, options: TextureOptions) -> TextureOptions {
+        match self {
+            UvMapping::Tile { .. } => TextureOptions {
+                address_mode: wgpu::AddressMode::Repeat,
+                ..options
+            },
+            _ => options,
+        }
     }
+}
 
-    pub fn new(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        format: wgpu::TextureFormat,
-        camera_bind_group_layout: &wgpu::BindGroupLayout,
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ShadingUniform {
+    letterbox_color: [f32; 4],
+    letterbox: u32,
+    reflectivity: f32,
+    /// Non-zero to light both sides of the paper, see
+    /// `Cloth::set_double_sided_shading`.
+    double_sided: u32,
+    /// Non-zero to shade every triangle with its own normal, see
+    /// `Cloth::set_flat_shading`.
+    flat_shading: u32,
+    backface_color: [f32; 4],
+    /// Non-zero to draw back faces in `backface_color`, see
+    /// `Cloth::set_backface_color`.
+    backface: u32,
+    _pad: [u32; 3],
+}
+
+impl ShadingUniform {
+    pub fn new() -> Self {
+        Self {
+            letterbox_color: [1.0, 1.0, 1.0, 1.0],
+            letterbox: 0,
+            reflectivity: 0.0,
+            double_sided: 1,
+            flat_shading: 0,
+            backface_color: [1.0, 1.0, 1.0, 1.0],
+            backface: 0,
+            _pad: [0; 3],
+        }
+    }
+}
+
+impl Default for ShadingUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single directional light on the cloth, see `Cloth::set_light`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct LightingUniform {
+    /// Points from the cloth toward the light, normalized.
+    direction: [f32; 3],
+    intensity: f32,
+    ambient: f32,
+    _pad: [f32; 3],
+}
+
+impl LightingUniform {
+    pub fn new() -> Self {
+        Self {
+            direction: Vector3::new(50.0, 6.0, 50.0).normalize().into(),
+            intensity: 1.0,
+            ambient: 0.0,
+            _pad: [0.0; 3],
+        }
+    }
+}
+
+impl Default for LightingUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The CPU side of a `Cloth`: the particles, the constraints holding them
+/// together and everything that moves them. It needs no GPU, so it can be
+/// built and stepped on its own with `ClothBuilder::build_simulation`.
+pub struct ClothSimulation {
+    particles: Vec<Particle>,
+    constraints: Vec<Constraint>,
+    /// Ranges of `constraints` that share no particles, see `color_constraints`.
+    #[cfg(feature = "parallel")]
+    constraint_colors: Vec<Range<usize>>,
+    /// Scratch space for one color's corrections.
+    #[cfg(feature = "parallel")]
+    corrections: Vec<(Vector3<f32>, Vector3<f32>)>,
+    edges: EdgeSet,
+    tear_threshold: f32,
+    gravity: Vector3<f32>,
+    wind: WindModel,
+    /// Only push on triangles whose front faces into the wind, see
+    /// `set_wind_front_faces_only`.
+    wind_front_faces_only: bool,
+    /// Seconds simulated so far, drives `WindModel::Gust`.
+    sim_time: f32,
+    damping: f32,
+    max_velocity: f32,
+    colliders: Vec<Sphere>,
+    floor_y: Option<f32>,
+    /// Check for non-finite particles after every step, see `set_safe_mode`.
+    safe_mode: bool,
+    constraint_iterations: usize,
+    structural_stiffness: f32,
+    bending_stiffness: f32,
+    view_right: Vector3<f32>,
+    view_up: Vector3<f32>,
+
+    num_particles_width: usize,
+    num_particles_height: usize,
+    width: f32,
+    height: f32,
+    /// Set when a tear changes which triangles are drawn.
+    indices_dirty: bool,
+    /// Particle pairs whose link tore since the last `take_torn`.
+    torn: Vec<(usize, usize)>,
+    /// Which particles `reset` pins again.
+    pin_layout: PinLayout,
+}
+
+/// A `ClothSimulation` and the GPU resources it's drawn with. The simulation's
+/// methods are reachable through `Deref`.
+pub struct Cloth {
+    pos: Vector3<f32>,
+    old_pos: Vector3<f32>,
+    acceleration: Vector3<f32>,
+    sim: ClothSimulation,
+    uv_mapping: UvMapping,
+
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    depth_compare: wgpu::CompareFunction,
+    pipeline: wgpu::RenderPipeline,
+    /// Draws triangle edges only, `None` unless `set_wireframe` turned it on.
+    wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    vertex_buffer: wgpu::Buffer,
+    vertex_normal_buffer: wgpu::Buffer,
+    tex_coord_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    diffuse_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture: Texture,
+    /// The encoded image `texture` was loaded from.
+    image_bytes: Vec<u8>,
+    shading: ShadingUniform,
+    shading_buffer: wgpu::Buffer,
+    lighting: LightingUniform,
+    lighting_buffer: wgpu::Buffer,
+    shading_bind_group: wgpu::BindGroup,
+    environment_bind_group_layout: wgpu::BindGroupLayout,
+    environment_bind_group: wgpu::BindGroup,
+    default_environment: Texture,
+    outline: Outline,
+    #[cfg(feature = "compute")]
+    gpu_solver: Option<ComputeSolver>,
+
+    vertices: Vec<Vertex>,
+    normals: Vec<Vertex>,
+    indices: Vec<u32>,
+    /// How textures are sampled, before `UvMapping` picks the address mode.
+    texture_options: TextureOptions,
+}
+
+impl std::ops::Deref for Cloth {
+    type Target = ClothSimulation;
+
+    fn deref(&self) -> &ClothSimulation {
+        &self.sim
+    }
+}
+
+impl std::ops::DerefMut for Cloth {
+    fn deref_mut(&mut self) -> &mut ClothSimulation {
+        &mut self.sim
+    }
+}
+
+impl ClothSimulation {
+    pub fn gravity(&self) -> Vector3<f32> {
+        self.gravity
+    }
+
+    /// Sets the acceleration applied to every particle each step.
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.gravity = gravity;
+    }
+
+    /// The wind blowing right now.
+    pub fn wind(&self) -> Vector3<f32> {
+        self.wind.at(self.sim_time)
+    }
+
+    /// Sets a steady wind blowing against the sheet, which pushes hardest on
+    /// triangles facing into it.
+    pub fn set_wind(&mut self, wind: Vector3<f32>) {
+        self.wind = WindModel::Constant(wind);
+    }
+
+    pub fn set_wind_model(&mut self, wind: WindModel) {
+        self.wind = wind;
+    }
+
+    /// Lets the wind push only on triangles whose front side faces into it,
+    /// like a sail that only catches wind on one side. Off by default, where
+    /// either side does.
+    pub fn set_wind_front_faces_only(&mut self, front_faces_only: bool) {
+        self.wind_front_faces_only = front_faces_only;
+    }
+
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    /// Sets the share of its velocity each particle loses every step, from 0
+    /// to 1. Higher values make the paper settle faster, as if moving through
+    /// something thicker than air; 0.01 by default.
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    /// Caps how far a particle can travel in one step, which keeps a fast
+    /// drag or strong wind from flinging the sheet off to infinity.
+    pub fn set_max_velocity(&mut self, max_velocity: f32) {
+        self.max_velocity = max_velocity.max(0.0);
+    }
+
+    pub fn tear_threshold(&self) -> f32 {
+        self.tear_threshold
+    }
+
+    /// Sets how far, relative to their rest length, constraints can be
+    /// stretched before the paper rips. Lower values tear more easily.
+    pub fn set_tear_threshold(&mut self, max_stretch_ratio: f32) {
+        self.tear_threshold = max_stretch_ratio;
+        for constraint in self.constraints.iter_mut() {
+            constraint.max_stretch_ratio = max_stretch_ratio;
+        }
+    }
+
+    /// Pins (`moveable = false`) or releases the particle at `(x, y)`. Does
+    /// nothing if it's outside the grid.
+    pub fn set_moveable(&mut self, x: usize, y: usize, moveable: bool) {
+        if x >= self.num_particles_width || y >= self.num_particles_height {
+            return;
+        }
+        self.particle_mut(x, y).is_movable = moveable;
+    }
+
+    /// Pins the particle at `(x, y)` if it's free and frees it if it's pinned.
+    pub fn toggle_pin(&mut self, x: usize, y: usize) {
+        if x >= self.num_particles_width || y >= self.num_particles_height {
+            return;
+        }
+        let particle = self.particle_mut(x, y);
+        particle.is_movable = !particle.is_movable;
+    }
+
+    /// Lays out the particles as a flat sheet pinned by `pin_layout` and
+    /// connects them with constraints.
+    fn create_grid(
         width: f32,
         height: f32,
         num_particles_width: usize,
         num_particles_height: usize,
-    ) -> Self {
+        pin_layout: PinLayout,
+    ) -> (Vec<Particle>, Vec<Constraint>) {
         let mut particles: Vec<Particle> =
             vec![Default::default(); num_particles_height * num_particles_width];
         let mut constraints = vec![];
@@ -239,12 +1476,13 @@ impl Cloth {
         fn make_constraint(
             p1: usize,
             p2: usize,
+            kind: ConstraintKind,
             particles: &mut [Particle],
             constraints: &mut Vec<Constraint>,
         ) {
             let p1_pos = particles[p1].position;
             let p2_pos = particles[p2].position;
-            constraints.push(Constraint::new(p1, p2, (p1_pos - p2_pos).magnitude()));
+            constraints.push(Constraint::new(p1, p2, (p1_pos - p2_pos).magnitude(), kind));
         }
 
         // creating particles in a grid of particles from (0,0,0) to (width,-height,0)
@@ -264,6 +1502,7 @@ impl Cloth {
                     acceleration: vec3(0.0, 0.0, 0.0),
                     accumulated_normal: vec3(0.0, 0.0, 0.0),
                     is_movable: true,
+                    inv_mass: 1.0,
 
                     tex_coords: vec2(pos.x / width, pos.y.abs() / height),
                 };
@@ -277,6 +1516,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x + 1, y),
+                        ConstraintKind::Structural,
                         &mut particles,
                         &mut constraints,
                     );
@@ -285,6 +1525,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x, y + 1),
+                        ConstraintKind::Structural,
                         &mut particles,
                         &mut constraints,
                     );
@@ -293,6 +1534,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x + 1, y + 1),
+                        ConstraintKind::Structural,
                         &mut particles,
                         &mut constraints,
                     );
@@ -301,6 +1543,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x + 1, y),
                         get_particle_idx(x, y + 1),
+                        ConstraintKind::Structural,
                         &mut particles,
                         &mut constraints,
                     );
@@ -315,6 +1558,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x + 2, y),
+                        ConstraintKind::Bending,
                         &mut particles,
                         &mut constraints,
                     );
@@ -323,6 +1567,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x, y + 2),
+                        ConstraintKind::Bending,
                         &mut particles,
                         &mut constraints,
                     );
@@ -331,6 +1576,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x, y),
                         get_particle_idx(x + 2, y + 2),
+                        ConstraintKind::Bending,
                         &mut particles,
                         &mut constraints,
                     );
@@ -339,6 +1585,7 @@ impl Cloth {
                     make_constraint(
                         get_particle_idx(x + 2, y),
                         get_particle_idx(x, y + 2),
+                        ConstraintKind::Bending,
                         &mut particles,
                         &mut constraints,
                     );
@@ -346,204 +1593,169 @@ impl Cloth {
             }
         }
 
-        for i in 0..3 {
-            particles[get_particle_idx(i, 0)].offset_pos(vec3(0.5, 0.0, 0.0)); // moving the particle a bit towards the center, to make it hang more natural - because I like it ;)
-            particles[get_particle_idx(i, 0)].make_unmovable();
-            particles[get_particle_idx(i, 0)].offset_pos(vec3(-0.5, 0.0, 0.0)); // moving the particle a bit towards the center, to make it hang more natural - because I like it ;)
-            particles[get_particle_idx(num_particles_width - 1 - i, 0)].make_unmovable();
+        let mut pin_corners = |y: usize| {
+            for i in 0..3 {
+                particles[get_particle_idx(i, y)].offset_pos(vec3(0.5, 0.0, 0.0)); // moving the particle a bit towards the center, to make it hang more natural - because I like it ;)
+                particles[get_particle_idx(i, y)].make_unmovable();
+                particles[get_particle_idx(i, y)].offset_pos(vec3(-0.5, 0.0, 0.0)); // moving the particle a bit towards the center, to make it hang more natural - because I like it ;)
+                particles[get_particle_idx(num_particles_width - 1 - i, y)].make_unmovable();
+            }
+        };
+        match pin_layout {
+            PinLayout::TopCorners => pin_corners(0),
+            PinLayout::FourCorners => {
+                pin_corners(0);
+                pin_corners(num_particles_height - 1);
+            }
+            PinLayout::TopEdge => {
+                for x in 0..num_particles_width {
+                    particles[get_particle_idx(x, 0)].make_unmovable();
+                }
+            }
+            PinLayout::None => (),
         }
 
-        let mut vertices = vec![];
-        let mut normals = vec![];
-        let mut tex_coord = vec![];
-
-        let bytes = include_bytes!("tweet.png");
-        // let bytes = include_bytes!("tweet2.png");
-        let texture =
-            Texture::from_bytes(device, queue, bytes, "tweet img").expect("To load image");
-
-        let (pipeline, vertex_buffer, vertex_normal_buffer, tex_coord_buffer, diffuse_bind_group) =
-            Self::create_render_pipeline(
-                device,
-                queue,
-                format,
-                &texture,
-                camera_bind_group_layout,
-                &mut vertices,
-                &mut normals,
-                &mut tex_coord,
-                &particles,
-                &constraints,
-                num_particles_width,
-                num_particles_height,
-            );
-
-        Self {
-            particles,
-            constraints,
-            old_pos: (0.0, 0.0, 0.0).into(),
-            pos: (0.0, 0.0, 0.0).into(),
-            acceleration: (1.0, 1.0, 0.0).into(),
-
-            num_particles_width,
-            num_particles_height,
-
-            pipeline,
-            vertex_buffer,
-            vertex_normal_buffer,
-            tex_coord_buffer,
-            diffuse_bind_group,
-            texture,
+        (particles, constraints)
+    }
 
-            vertices,
-            normals,
-            tex_coord,
+    pub fn save_state(&self) -> ClothState {
+        ClothState {
+            num_particles_width: self.num_particles_width,
+            num_particles_height: self.num_particles_height,
+            positions: self.particles.iter().map(|p| p.position.into()).collect(),
+            old_positions: self
+                .particles
+                .iter()
+                .map(|p| p.old_position.into())
+                .collect(),
+            is_movable: self.particles.iter().map(|p| p.is_movable).collect(),
+            inv_mass: self.particles.iter().map(|p| p.inv_mass).collect(),
+            torn: self.torn_constraints(),
         }
     }
 
-    fn create_render_pipeline(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        format: wgpu::TextureFormat,
-        texture: &Texture,
-        camera_bind_group_layout: &wgpu::BindGroupLayout,
-
-        vertices: &mut Vec<Vertex>,
-        normals: &mut Vec<Vertex>,
-        tex_coord: &mut Vec<Vertex2>,
-
-        particles: &[Particle],
-        constraints: &[Constraint],
+    /// The particle pairs linked in a fresh grid that aren't anymore.
+    fn torn_constraints(&self) -> Vec<[usize; 2]> {
+        let alive: std::collections::HashSet<(usize, usize)> =
+            self.constraints.iter().map(|c| (c.p1, c.p2)).collect();
+        let (_, constraints) = Self::create_grid(
+            self.width,
+            self.height,
+            self.num_particles_width,
+            self.num_particles_height,
+            self.pin_layout,
+        );
+        constraints
+            .into_iter()
+            .filter(|c| !alive.contains(&(c.p1, c.p2)))
+            .map(|c| [c.p1, c.p2])
+            .collect()
+    }
 
-        num_particles_width: usize,
-        num_particles_height: usize,
-    ) -> (
-        wgpu::RenderPipeline,
-        wgpu::Buffer,
-        wgpu::Buffer,
-        wgpu::Buffer,
-        wgpu::BindGroup,
-    ) {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Particle shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("particle.wgsl").into()),
-        });
+    /// Restores a state from `save_state`, which must come from a cloth with
+    /// the same grid dimensions. Constraints torn in the state are torn again,
+    /// and any torn since it was saved are put back.
+    pub fn load_state(&mut self, state: &ClothState) -> Result<()> {
+        if state.num_particles_width != self.num_particles_width
+            || state.num_particles_height != self.num_particles_height
+        {
+            bail!(
+                "Cloth state is {}x{} but the cloth is {}x{}",
+                state.num_particles_width,
+                state.num_particles_height,
+                self.num_particles_width,
+                self.num_particles_height
+            );
+        }
+        let len = self.particles.len();
+        if state.positions.len() != len
+            || state.old_positions.len() != len
+            || state.is_movable.len() != len
+            || state.inv_mass.len() != len
+        {
+            bail!("Cloth state doesn't have one entry per particle");
+        }
+        if let Some(&[p1, p2]) = state.torn.iter().find(|&&[p1, p2]| p1 >= len || p2 >= len) {
+            bail!("Cloth state tears {}-{}, past the last particle", p1, p2);
+        }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Texture bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+        for (i, particle) in self.particles.iter_mut().enumerate() {
+            particle.position = state.positions[i].into();
+            particle.old_position = state.old_positions[i].into();
+            particle.is_movable = state.is_movable[i];
+            particle.inv_mass = state.inv_mass[i];
+            particle.acceleration = vec3(0.0, 0.0, 0.0);
+        }
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(
-                        &texture.sampler.as_ref().expect("Texture to have sampler"),
-                    ),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+        let (_, mut constraints) = Self::create_grid(
+            self.width,
+            self.height,
+            self.num_particles_width,
+            self.num_particles_height,
+            self.pin_layout,
+        );
+        let torn: std::collections::HashSet<(usize, usize)> =
+            state.torn.iter().map(|&[p1, p2]| (p1, p2)).collect();
+        constraints.retain(|c| !torn.contains(&(c.p1, c.p2)));
+        self.configure_constraints(&mut constraints);
+        self.constraints = constraints;
+        #[cfg(feature = "parallel")]
+        self.recolor_constraints();
+
+        self.edges = EdgeSet::new(self.num_particles_width, self.num_particles_height);
+        for &[p1, p2] in &state.torn {
+            self.edges.tear(p1, p2);
+        }
+        self.indices_dirty = true;
+        self.torn.clear();
+        self.update_normals();
+        Ok(())
+    }
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Particle pipeline layout"),
-            bind_group_layouts: &[camera_bind_group_layout, &texture_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    /// Puts the sheet back the way it was built, untorn and at rest. Texture
+    /// coordinates are carried over, so a `UvMapping` set on the `Cloth`
+    /// survives resets, including the ones `sanitize` does.
+    pub fn reset(&mut self) {
+        let (mut particles, mut constraints) = Self::create_grid(
+            self.width,
+            self.height,
+            self.num_particles_width,
+            self.num_particles_height,
+            self.pin_layout,
+        );
+        self.configure_constraints(&mut constraints);
+        for (particle, old) in particles.iter_mut().zip(&self.particles) {
+            particle.tex_coords = old.tex_coords;
+        }
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Particle render pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc(), Self::normal_desc(), Self::tex_coord_desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                // cull_mode: Some(wgpu::Face::Back),
-                cull_mode: None,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: SAMPLE_COUNT as u32,
-                ..Default::default()
-            },
-            multiview: None,
-        });
+        self.particles = particles;
+        self.constraints = constraints;
+        #[cfg(feature = "parallel")]
+        self.recolor_constraints();
+        self.edges = EdgeSet::new(self.num_particles_width, self.num_particles_height);
+        self.indices_dirty = true;
+        self.torn.clear();
+        self.sim_time = 0.0;
+        self.update_normals();
+    }
 
-        Self::fill_vertices(
-            particles,
-            vertices,
-            normals,
-            tex_coord,
-            num_particles_width,
-            num_particles_height,
-        );
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-        let vertex_normal_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: "Vertex Normal Buffer".into(),
-            contents: bytemuck::cast_slice(&normals),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
-        let tex_coord_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: "Texture Coord Buffer".into(),
-            contents: bytemuck::cast_slice(&tex_coord),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-        });
+    /// Gives freshly built constraints the current tear threshold and stiffness.
+    fn configure_constraints(&self, constraints: &mut [Constraint]) {
+        for constraint in constraints.iter_mut() {
+            constraint.max_stretch_ratio = self.tear_threshold;
+            constraint.stiffness = match constraint.kind {
+                ConstraintKind::Structural => self.structural_stiffness,
+                ConstraintKind::Bending => self.bending_stiffness,
+            };
+        }
+    }
 
-        (
-            pipeline,
-            vertex_buffer,
-            vertex_normal_buffer,
-            tex_coord_buffer,
-            diffuse_bind_group,
-        )
+    fn center(&self) -> Vector3<f32> {
+        let sum = self
+            .particles
+            .iter()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |acc, p| acc + p.position);
+        sum / self.particles.len() as f32
     }
 
     fn calc_triangle_normal(p1: &Particle, p2: &Particle, p3: &Particle) -> Vector3<f32> {
@@ -571,66 +1783,33 @@ impl Cloth {
             &self.particles[p3i],
         );
 
-        let d = normal.normalize();
-        let force = normal * d.dot(dir);
+        // Collapsed by a tear or squashed flat, it has no direction to push in
+        let length = normal.magnitude();
+        if length == 0.0 {
+            return;
+        }
+        let normal = normal / length;
+        let pressure = normal.dot(dir);
+        if self.wind_front_faces_only && pressure > 0.0 {
+            return;
+        }
+        let force = normal * (pressure * self.wind_weight());
         self.particles[p1i].add_force(force);
         self.particles[p2i].add_force(force);
         self.particles[p3i].add_force(force);
     }
 
-    fn fill_vertices(
-        particles: &[Particle],
-        vertices: &mut Vec<Vertex>,
-        normals: &mut Vec<Vertex>,
-        tex_coord: &mut Vec<Vertex2>,
-        num_particles_width: usize,
-        num_particles_height: usize,
-    ) {
-        vertices.clear();
-        normals.clear();
-        tex_coord.clear();
-
-        let get_particle_idx = |x: usize, y: usize| -> usize { y * num_particles_width + x };
-
-        for x in 0..num_particles_width - 1 {
-            for y in 0..num_particles_height - 1 {
-                let tmp = [
-                    &particles[get_particle_idx(x + 1, y)],
-                    &particles[get_particle_idx(x, y)],
-                    &particles[get_particle_idx(x, y + 1)],
-                    //
-                    &particles[get_particle_idx(x + 1, y + 1)],
-                    &particles[get_particle_idx(x + 1, y)],
-                    &particles[get_particle_idx(x, y + 1)],
-                ];
-
-                vertices.extend(tmp.iter().map(|p| Vertex {
-                    position: p.position.into(),
-                    // _pad: 0.0,
-                }));
-
-                tex_coord.extend(tmp.iter().map(|p| Vertex2 {
-                    position: p.tex_coords.into(),
-                }));
-
-                normals.extend(tmp.iter().map(|p| Vertex {
-                    position: p.accumulated_normal.normalize().into(),
-                    // _pad: 0.0,
-                }));
-            }
-        }
-    }
-
     pub fn update(&mut self, timestep: f32) {
         // gravity
         // self.add_force(vec3(0.0, -2.8, 0.0) * timestep);
-        self.add_force(vec3(0.0, -0.8, 0.0) * timestep);
-        self.add_wind_force(vec3(50.5, 0.0, 0.2) * timestep);
+        self.add_force(self.gravity * timestep);
+        self.add_wind_force(self.wind.at(self.sim_time) * timestep);
         // self.add_wind_force(vec3(10.5, 0.0, 0.2) * timestep);
         // self.add_wind_force(vec3(00.5, -40.0, -10.2) * timestep);
         // self.add_wind_force(vec3(10.5, 0.0, 100.2) * timestep);
         // self.add_wind_force(vec3(0.5, 0.0, 0.2) * timestep);
         self.time_step(timestep);
+        self.sim_time += timestep;
     }
 
     fn particle_mut(&mut self, x: usize, y: usize) -> &mut Particle {
@@ -668,21 +1847,37 @@ impl Cloth {
         }
     }
 
-    pub fn render<'a, 'b>(
-        &'a self,
-        camera_bind_group: &'a wgpu::BindGroup,
-        render_pass: &mut wgpu::RenderPass<'a>,
-    ) {
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.diffuse_bind_group, &[]);
-
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.vertex_normal_buffer.slice(..));
-        render_pass.set_vertex_buffer(2, self.tex_coord_buffer.slice(..));
+    /// Gives the particle at `(x, y)` a mass relative to the default of 1,
+    /// e.g. to weigh down the hem. A mass of 0 or less pins it in place.
+    /// Does nothing if it's outside the grid.
+    pub fn set_particle_mass(&mut self, x: usize, y: usize, mass: f32) {
+        if x >= self.num_particles_width || y >= self.num_particles_height {
+            return;
+        }
+        let particle = self.particle_mut(x, y);
+        if mass > 0.0 {
+            particle.inv_mass = 1.0 / mass;
+        } else {
+            particle.inv_mass = 0.0;
+            particle.make_unmovable();
+        }
+    }
 
-        // println!("VERTEX: {:?}", self.particles.len());
-        render_pass.draw(0..self.vertices.len() as u32, 0..1);
+    /// Pushes every particle within `radius` world units of `center` straight
+    /// away from it, strongest at the center and fading to nothing at the
+    /// edge. Returns how many particles were pushed.
+    pub fn apply_impulse(&mut self, center: Vector3<f32>, strength: f32, radius: f32) -> usize {
+        let mut affected = 0;
+        for particle in self.particles.iter_mut() {
+            let offset = particle.position - center;
+            let dist = offset.magnitude();
+            if dist >= radius || dist == 0.0 {
+                continue;
+            }
+            particle.add_force(offset / dist * strength * (1.0 - dist / radius));
+            affected += 1;
+        }
+        affected
     }
 
     pub fn add_force(&mut self, force: Vector3<f32>) {
@@ -691,10 +1886,25 @@ impl Cloth {
         }
     }
 
+    /// Twice a triangle's rest area, the length of its unnormalized normal
+    /// before the sheet moves, see `add_wind_force`.
+    fn wind_weight(&self) -> f32 {
+        (self.width / self.num_particles_width as f32)
+            * (self.height / self.num_particles_height as f32)
+    }
+
     fn get_particle_idx(&self, x: usize, y: usize) -> usize {
         y * self.num_particles_width + x
     }
 
+    /// Wind is modelled as pressure on each triangle: it pushes along the
+    /// triangle's normal, in proportion to how squarely the triangle faces
+    /// `dir`, times its area at rest. Every corner gets that whole push,
+    /// which `DEFAULT_WIND` is tuned for.
+    ///
+    /// Using the rest area rather than the current one keeps a triangle the
+    /// wind has stretched from catching ever more of it, which used to feed
+    /// back into itself at high wind speeds.
     pub fn add_wind_force(&mut self, dir: Vector3<f32>) {
         for x in 0..self.num_particles_width - 1 {
             for y in 0..self.num_particles_height - 1 {
@@ -715,62 +1925,1393 @@ impl Cloth {
     }
 
     pub fn time_step(&mut self, timestep: f32) {
-        for _ in 0..CONSTRAINT_ITERATIONS {
-            for constraint in self.constraints.iter_mut() {
-                constraint.satisfy(&mut self.particles);
+        for _ in 0..self.constraint_iterations {
+            self.satisfy_constraints();
+            self.collide();
+        }
+
+        let num_constraints = self.constraints.len();
+        let particles = &self.particles;
+        let edges = &mut self.edges;
+        let torn = &mut self.torn;
+        self.constraints.retain(|constraint| {
+            if constraint.is_torn(particles) {
+                edges.tear(constraint.p1, constraint.p2);
+                torn.push((constraint.p1, constraint.p2));
+                return false;
             }
+            true
+        });
+        if self.constraints.len() != num_constraints {
+            self.indices_dirty = true;
+            #[cfg(feature = "parallel")]
+            self.recolor_constraints();
         }
 
         for particle in self.particles.iter_mut() {
-            particle.time_step(timestep);
+            particle.time_step(timestep, self.damping, self.max_velocity);
+        }
+        self.collide();
+
+        if let Some(floor_y) = self.floor_y {
+            for particle in self.particles.iter_mut() {
+                if !particle.is_movable || particle.position.y >= floor_y {
+                    continue;
+                }
+                particle.position.y = floor_y;
+                particle.old_position.y = floor_y;
+                particle.old_position.x +=
+                    (particle.position.x - particle.old_position.x) * FLOOR_FRICTION;
+                particle.old_position.z +=
+                    (particle.position.z - particle.old_position.z) * FLOOR_FRICTION;
+            }
+        }
+
+        if self.safe_mode {
+            self.sanitize();
         }
     }
 
-    pub fn update_wgpu(&mut self, queue: &wgpu::Queue) {
-        Self::fill_vertices(
-            &self.particles,
-            &mut self.vertices,
-            &mut self.normals,
-            &mut self.tex_coord,
-            self.num_particles_width,
-            self.num_particles_height,
-        );
+    #[cfg(not(feature = "parallel"))]
+    fn satisfy_constraints(&mut self) {
+        for constraint in self.constraints.iter() {
+            constraint.satisfy(&mut self.particles);
+        }
+    }
 
-        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
-        queue.write_buffer(
-            &self.vertex_normal_buffer,
-            0,
-            bytemuck::cast_slice(&self.normals),
-        );
+    /// Solves one color at a time: the corrections within a color touch
+    /// different particles, so they're worked out in parallel and then
+    /// applied, which gives the same result as solving them one by one.
+    #[cfg(feature = "parallel")]
+    fn satisfy_constraints(&mut self) {
+        use rayon::prelude::*;
+
+        for color in self.constraint_colors.iter() {
+            let constraints = &self.constraints[color.clone()];
+            let particles = &self.particles;
+            constraints
+                .par_iter()
+                .map(|constraint| constraint.correction(particles))
+                .collect_into_vec(&mut self.corrections);
+
+            for (constraint, &(offset_p1, offset_p2)) in
+                constraints.iter().zip(self.corrections.iter())
+            {
+                self.particles[constraint.p1].offset_pos(offset_p1);
+                self.particles[constraint.p2].offset_pos(offset_p2);
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    fn recolor_constraints(&mut self) {
+        let constraints = std::mem::take(&mut self.constraints);
+        (self.constraints, self.constraint_colors) =
+            color_constraints(constraints, self.particles.len());
+    }
+
+    pub fn constraint_iterations(&self) -> usize {
+        self.constraint_iterations
+    }
+
+    /// Sets how many times per step every constraint is relaxed. More passes
+    /// make the cloth stiffer at the cost of CPU time.
+    pub fn set_constraint_iterations(&mut self, constraint_iterations: usize) {
+        self.constraint_iterations = constraint_iterations.max(1);
+    }
+
+    /// Scales how strongly neighboring particles are held at their rest
+    /// distance, from 0 (none) to 1.
+    pub fn set_structural_stiffness(&mut self, stiffness: f32) {
+        self.structural_stiffness = stiffness.clamp(0.0, 1.0);
+        self.set_stiffness(ConstraintKind::Structural, self.structural_stiffness);
+    }
+
+    /// Scales how strongly the sheet resists bending, from 0 (floppy tissue)
+    /// to 1 (stiff card).
+    pub fn set_bending_stiffness(&mut self, stiffness: f32) {
+        self.bending_stiffness = stiffness.clamp(0.0, 1.0);
+        self.set_stiffness(ConstraintKind::Bending, self.bending_stiffness);
+    }
+
+    fn set_stiffness(&mut self, kind: ConstraintKind, stiffness: f32) {
+        for constraint in self.constraints.iter_mut().filter(|c| c.kind == kind) {
+            constraint.stiffness = stiffness;
+        }
+    }
+
+    /// Stops particles from falling below `floor_y`, `None` removes the floor.
+    pub fn set_floor(&mut self, floor_y: Option<f32>) {
+        self.floor_y = floor_y;
+    }
+
+    pub fn safe_mode(&self) -> bool {
+        self.safe_mode
+    }
+
+    /// Scans every particle after each step and puts back any whose position
+    /// went NaN or infinite before it spreads to the rest of the sheet. On by
+    /// default in debug builds only, as it's a full pass over the particles.
+    pub fn set_safe_mode(&mut self, safe_mode: bool) {
+        self.safe_mode = safe_mode;
+    }
+
+    /// Moves non-finite particles back to their last finite position, which
+    /// also stops them. If one has no finite position left, or too many went
+    /// bad at once, there's nothing sensible to patch and the sheet is reset.
+    fn sanitize(&mut self) {
+        let is_finite = |v: Vector3<f32>| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        let mut corrupt = 0;
+        let mut lost = false;
+        for particle in self.particles.iter_mut() {
+            match (
+                is_finite(particle.position),
+                is_finite(particle.old_position),
+            ) {
+                (true, true) => continue,
+                (false, true) => particle.position = particle.old_position,
+                (true, false) => particle.old_position = particle.position,
+                (false, false) => lost = true,
+            }
+            particle.acceleration = Vector3::new(0.0, 0.0, 0.0);
+            corrupt += 1;
+        }
+        if corrupt == 0 {
+            return;
+        }
+
+        if lost || corrupt as f32 > self.particles.len() as f32 * MAX_CORRUPT_PARTICLES {
+            log::warn!(
+                "{} of {} particles went non-finite, resetting the cloth",
+                corrupt,
+                self.particles.len()
+            );
+            self.reset();
+        } else {
+            log::warn!("Restored {} non-finite particles", corrupt);
+        }
+    }
+
+    fn collide(&mut self) {
+        for sphere in self.colliders.iter() {
+            for particle in self.particles.iter_mut() {
+                sphere.collide(particle);
+            }
+        }
+    }
+
+    pub fn add_sphere_collider(&mut self, sphere: Sphere) {
+        self.colliders.push(sphere);
+    }
+
+    pub fn clear_colliders(&mut self) {
+        self.colliders.clear();
+    }
+
+    /// Every particle, row by row from the top left.
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn particle_at(&self, x: usize, y: usize) -> Option<&Particle> {
+        if x >= self.num_particles_width || y >= self.num_particles_height {
+            return None;
+        }
+        self.particles.get(self.get_particle_idx(x, y))
+    }
+
+    /// The `(min, max)` corners of the box around every particle.
+    pub fn bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let first = self.particles[0].position;
+        self.particles
+            .iter()
+            .fold((first, first), |(min, max), particle| {
+                let p = particle.position;
+                (
+                    vec3(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    vec3(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                )
+            })
+    }
+
+    /// The particle indices of every link that tore since the last call.
+    pub fn take_torn(&mut self) -> Vec<(usize, usize)> {
+        std::mem::take(&mut self.torn)
+    }
+
+    /// Maps an index into the vertex buffer (one vertex per particle) back to
+    /// the particle's grid coordinates.
+    pub fn particle_coords(&self, vertex: u32) -> (usize, usize) {
+        let vertex = vertex as usize;
+        (
+            vertex % self.num_particles_width,
+            vertex / self.num_particles_width,
+        )
     }
 
+    /// Tests `ray` against the mesh on the CPU. Slower and less exact than
+    /// `Picking`, but needs no render target.
     pub fn intersects(&self, ray: &Ray) -> Option<(usize, usize)> {
+        let mut closest: Option<(f32, [(usize, usize); 3])> = None;
+
         for x in 0..self.num_particles_width - 1 {
             for y in 0..self.num_particles_height - 1 {
-                if ray.triangle_intersection([
-                    vec_to_point(&self.particles[(self.get_particle_idx(x + 1, y))].position),
-                    vec_to_point(&self.particles[self.get_particle_idx(x, y)].position),
-                    vec_to_point(&self.particles[self.get_particle_idx(x, y + 1)].position),
-                ]) {
-                    return Some((x, y));
+                // Same triangles as `fill_indices`
+                let triangles = [
+                    [(x + 1, y), (x, y), (x, y + 1)],
+                    [(x + 1, y + 1), (x + 1, y), (x, y + 1)],
+                ];
+                let alive = self.edges.triangles_alive(x, y);
+                for (corners, alive) in triangles.into_iter().zip(alive) {
+                    if !alive {
+                        continue;
+                    }
+                    let hit = ray.triangle_intersection(corners.map(|(x, y)| {
+                        vec_to_point(&self.particles[self.get_particle_idx(x, y)].position)
+                    }));
+                    match (hit, closest) {
+                        (Some(t), Some((closest_t, _))) if t >= closest_t => {}
+                        (Some(t), _) => closest = Some((t, corners)),
+                        (None, _) => {}
+                    }
                 }
             }
         }
 
-        None
+        // Report whichever corner of the hit triangle is nearest the hit point
+        let (t, corners) = closest?;
+        let hit = ray.at(t);
+        corners.into_iter().min_by(|&(ax, ay), &(bx, by)| {
+            let a = (self.particles[self.get_particle_idx(ax, ay)].position - hit).magnitude2();
+            let b = (self.particles[self.get_particle_idx(bx, by)].position - hit).magnitude2();
+            a.total_cmp(&b)
+        })
     }
 
+    /// Drags the triangle at grid square `(x, y)` by a screen-space delta,
+    /// moving it in the plane spanned by the camera's right and up axes (see
+    /// `set_view_axes`). Out of range coordinates are clamped to the grid and
+    /// pinned particles stay put.
     pub fn mouse_force(&mut self, x: usize, y: usize, dx: f32, dy: f32) {
+        let x = x.min(self.num_particles_width - 2);
+        let y = y.min(self.num_particles_height - 2);
+        let offset = (self.view_right * dx + self.view_up * dy) * MOUSE_DRAG_SCALE;
+
         let i0 = self.get_particle_idx(x + 1, y);
         let i1 = self.get_particle_idx(x, y);
         let i2 = self.get_particle_idx(x, y + 1);
 
-        self.particles[i0].add_force(vec3(dx, dy, 0.0));
-        self.particles[i1].add_force(vec3(dx, dy, 0.0));
-        self.particles[i2].add_force(vec3(dx, dy, 0.0));
+        self.particles[i0].offset_pos(offset);
+        self.particles[i1].offset_pos(offset);
+        self.particles[i2].offset_pos(offset);
+    }
+
+    /// Like `mouse_force`, but pulls every particle within `radius` grid cells
+    /// of `(center_x, center_y)`, fading out linearly towards the edge.
+    pub fn drag(&mut self, center_x: usize, center_y: usize, dx: f32, dy: f32, radius: f32) {
+        let offset = (self.view_right * dx + self.view_up * dy) * MOUSE_DRAG_SCALE;
+        let radius = radius.max(1.0);
+        let reach = radius.ceil() as usize;
+
+        let x_range =
+            center_x.saturating_sub(reach)..=(center_x + reach).min(self.num_particles_width - 1);
+        for x in x_range {
+            let y_range = center_y.saturating_sub(reach)
+                ..=(center_y + reach).min(self.num_particles_height - 1);
+            for y in y_range {
+                let dist = vec2(x as f32 - center_x as f32, y as f32 - center_y as f32).magnitude();
+                if dist >= radius {
+                    continue;
+                }
+                self.particle_mut(x, y)
+                    .offset_pos(offset * (1.0 - dist / radius));
+            }
+        }
+    }
+
+    /// Sets the world-space directions a mouse drag moves particles in,
+    /// should follow the camera.
+    pub fn set_view_axes(&mut self, right: Vector3<f32>, up: Vector3<f32>) {
+        self.view_right = right;
+        self.view_up = up;
     }
 }
 
-fn vec_to_point(vec: &Vector3<f32>) -> Point3<f32> {
-    Point3::new(vec.x, vec.y, vec.z)
+impl Cloth {
+    const INDICES: &[u16] = &[0, 2, 1];
+    const NORMAL_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![1=>Float32x3];
+    const TEX_COORD_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![2=>Float32x2];
+    fn normal_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::NORMAL_ATTRIBUTES,
+        }
+    }
+    fn tex_coord_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex2>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::TEX_COORD_ATTRIBUTES,
+        }
+    }
+
+    /// Builds the sheet `builder` describes, see `ClothBuilder::build`.
+    fn new(
+        builder: &ClothBuilder,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let ClothBuilder {
+            width,
+            height,
+            particles_x: num_particles_width,
+            particles_y: num_particles_height,
+            texture_options,
+            ..
+        } = *builder;
+        let mut sim = builder.build_simulation();
+
+        let mut vertices = vec![];
+        let mut normals = vec![];
+        let mut indices = vec![];
+        let mut tex_coord = vec![];
+
+        let uv_mapping = UvMapping::default();
+        let sampling = uv_mapping.texture_options(texture_options);
+
+        // A bad image shouldn't stop the whole thing, fall back to the tweet
+        let load = |image: &ImageSource| -> Result<(Texture, Vec<u8>)> {
+            let bytes = image.read()?;
+            let texture = Texture::from_bytes(device, queue, &bytes, "cloth texture", sampling)?;
+            Ok((texture, bytes))
+        };
+        let (texture, image_bytes) = builder
+            .image
+            .as_ref()
+            .and_then(|image| match load(image) {
+                Ok(loaded) => Some(loaded),
+                Err(err) => {
+                    log::warn!("Failed to load cloth image, using the default: {:?}", err);
+                    None
+                }
+            })
+            .unwrap_or_else(|| {
+                let bytes = include_bytes!("tweet.png");
+                // let bytes = include_bytes!("tweet2.png");
+                let texture = Texture::from_bytes(device, queue, bytes, "tweet img", sampling)
+                    .expect("To load image");
+                (texture, bytes.to_vec())
+            });
+
+        Self::map_tex_coords(
+            &mut sim.particles,
+            uv_mapping,
+            width / height,
+            texture.aspect(),
+            num_particles_width,
+            num_particles_height,
+        );
+
+        let texture_bind_group_layout = Self::create_texture_bind_group_layout(device);
+        let diffuse_bind_group =
+            Self::create_diffuse_bind_group(device, &texture_bind_group_layout, &texture);
+
+        let shading = ShadingUniform::new();
+        let shading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shading Buffer"),
+            contents: bytemuck::cast_slice(&[shading]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let lighting = LightingUniform::new();
+        let lighting_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lighting Buffer"),
+            contents: bytemuck::cast_slice(&[lighting]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // The lighting shares the shading group, all four bind groups the
+        // default limits allow are taken
+        let shading_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shading bind group layout"),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let shading_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shading bind group"),
+            layout: &shading_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: shading_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lighting_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let environment_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Environment bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        // Black until a skybox is set, reflectivity is 0 by default anyway
+        let black = [0u8, 0, 0, 255];
+        let default_environment = Texture::create_cubemap_from_rgba(
+            device,
+            queue,
+            1,
+            [
+                &black[..],
+                &black[..],
+                &black[..],
+                &black[..],
+                &black[..],
+                &black[..],
+            ],
+            "default environment",
+        );
+        let environment_bind_group = Self::create_diffuse_bind_group(
+            device,
+            &environment_bind_group_layout,
+            &default_environment,
+        );
+
+        let (shader, pipeline_layout, pipeline) = Self::create_render_pipeline(
+            device,
+            format,
+            sample_count,
+            &[
+                camera_bind_group_layout,
+                &texture_bind_group_layout,
+                &shading_bind_group_layout,
+                &environment_bind_group_layout,
+            ],
+        );
+
+        Self::fill_vertices(&sim.particles, &mut vertices, &mut normals);
+        Self::fill_tex_coords(&sim.particles, &mut tex_coord);
+        Self::fill_indices(
+            &sim.edges,
+            &mut indices,
+            num_particles_width,
+            num_particles_height,
+        );
+        let (vertex_buffer, vertex_normal_buffer, tex_coord_buffer, index_buffer) =
+            Self::create_buffers(device, &vertices, &normals, &tex_coord, &indices);
+
+        let outline = Outline::new(device, format, sample_count, camera_bind_group_layout);
+
+        Self {
+            old_pos: (0.0, 0.0, 0.0).into(),
+            pos: (0.0, 0.0, 0.0).into(),
+            acceleration: (1.0, 1.0, 0.0).into(),
+            sim,
+            uv_mapping,
+
+            shader,
+            pipeline_layout,
+            format,
+            sample_count,
+            depth_compare: DepthConfig::default().compare,
+            pipeline,
+            wireframe_pipeline: None,
+            vertex_buffer,
+            vertex_normal_buffer,
+            tex_coord_buffer,
+            index_buffer,
+            diffuse_bind_group,
+            texture_bind_group_layout,
+            texture,
+            image_bytes,
+            texture_options,
+            shading,
+            shading_buffer,
+            lighting,
+            lighting_buffer,
+            shading_bind_group,
+            environment_bind_group_layout,
+            environment_bind_group,
+            default_environment,
+            outline,
+            #[cfg(feature = "compute")]
+            gpu_solver: None,
+
+            vertices,
+            normals,
+            indices,
+        }
+    }
+
+    fn create_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Texture bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_diffuse_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        &texture.sampler.as_ref().expect("Texture to have sampler"),
+                    ),
+                },
+            ],
+            label: Some("diffuse_bind_group"),
+        })
+    }
+
+    fn map_tex_coords(
+        particles: &mut [Particle],
+        uv_mapping: UvMapping,
+        cloth_aspect: f32,
+        texture_aspect: f32,
+        num_particles_width: usize,
+        num_particles_height: usize,
+    ) {
+        for x in 0..num_particles_width {
+            for y in 0..num_particles_height {
+                let u = x as f32 / num_particles_width as f32;
+                let v = y as f32 / num_particles_height as f32;
+                particles[y * num_particles_width + x].tex_coords =
+                    uv_mapping.tex_coords(u, v, cloth_aspect, texture_aspect);
+            }
+        }
+    }
+
+    pub fn uv_mapping(&self) -> UvMapping {
+        self.uv_mapping
+    }
+
+    /// The encoded PNG or JPEG printed on the cloth.
+    pub fn image_bytes(&self) -> &[u8] {
+        &self.image_bytes
+    }
+
+    /// Sets the color shown around the texture when it is fitted with `FitMode::Contain`.
+    pub fn set_letterbox_color(&mut self, queue: &wgpu::Queue, color: Vector4<f32>) {
+        self.shading.letterbox_color = color.into();
+        self.write_shading(queue);
+    }
+
+    /// Blends in the environment reflected off the paper, 0 is matte and 1 is a mirror.
+    pub fn set_reflectivity(&mut self, queue: &wgpu::Queue, reflectivity: f32) {
+        self.shading.reflectivity = reflectivity.clamp(0.0, 1.0);
+        self.write_shading(queue);
+    }
+
+    /// With double-sided shading, which is on by default, the underside of
+    /// the paper is lit like the top instead of as if facing away from the
+    /// light, so folds don't go black.
+    pub fn set_double_sided_shading(&mut self, queue: &wgpu::Queue, double_sided: bool) {
+        self.shading.double_sided = double_sided as u32;
+        self.write_shading(queue);
+    }
+
+    pub fn double_sided_shading(&self) -> bool {
+        self.shading.double_sided != 0
+    }
+
+    /// Shades every triangle with its own geometric normal instead of normals
+    /// smoothed across the particles, for a faceted, folded-paper look.
+    pub fn set_flat_shading(&mut self, queue: &wgpu::Queue, flat_shading: bool) {
+        self.shading.flat_shading = flat_shading as u32;
+        self.write_shading(queue);
+    }
+
+    pub fn flat_shading(&self) -> bool {
+        self.shading.flat_shading != 0
+    }
+
+    /// Draws the back of the paper in a solid `color` instead of the texture
+    /// showing through mirrored, so folds read clearly. `None` goes back to
+    /// texturing both sides.
+    pub fn set_backface_color(&mut self, queue: &wgpu::Queue, color: Option<Vector4<f32>>) {
+        self.shading.backface = color.is_some() as u32;
+        if let Some(color) = color {
+            self.shading.backface_color = color.into();
+        }
+        self.write_shading(queue);
+    }
+
+    /// Sets the cubemap sampled for reflections, `None` reflects nothing.
+    pub fn set_environment(&mut self, device: &wgpu::Device, cubemap: Option<&Texture>) {
+        self.environment_bind_group = Self::create_diffuse_bind_group(
+            device,
+            &self.environment_bind_group_layout,
+            cubemap.unwrap_or(&self.default_environment),
+        );
+    }
+
+    /// Draws a solid `color` silhouette `width` world units wide around the
+    /// cloth, a width of 0 turns it off.
+    pub fn set_outline(&mut self, queue: &wgpu::Queue, color: Vector4<f32>, width: f32) {
+        self.outline.set(queue, color, width);
+        if self.outline.enabled() {
+            self.outline.set_center(queue, self.center());
+        }
+    }
+
+    /// Lights the cloth from `direction`, pointing from the cloth toward the
+    /// light, scaled by `intensity` on top of an even `ambient` level. The
+    /// default is a light off to the right at full intensity with no ambient;
+    /// an ambient of 1 and intensity of 0 gives a flat, unshaded look.
+    pub fn set_light(
+        &mut self,
+        queue: &wgpu::Queue,
+        direction: Vector3<f32>,
+        intensity: f32,
+        ambient: f32,
+    ) {
+        if direction.magnitude2() > 0.0 {
+            self.lighting.direction = direction.normalize().into();
+        }
+        self.lighting.intensity = intensity.max(0.0);
+        self.lighting.ambient = ambient.max(0.0);
+        queue.write_buffer(
+            &self.lighting_buffer,
+            0,
+            bytemuck::cast_slice(&[self.lighting]),
+        );
+    }
+
+    fn write_shading(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.shading_buffer,
+            0,
+            bytemuck::cast_slice(&[self.shading]),
+        );
+    }
+
+    /// Fits a single copy of the texture onto the sheet, correcting for aspect ratio.
+    pub fn set_uv_fit(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, fit: FitMode) {
+        self.set_uv_mapping(device, queue, UvMapping::Fit(fit));
+    }
+
+    /// Changes how the texture is laid out and re-uploads the texture coordinates.
+    pub fn set_uv_mapping(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        uv_mapping: UvMapping,
+    ) {
+        let sampling = uv_mapping.texture_options(self.texture_options);
+        if sampling != self.uv_mapping.texture_options(self.texture_options) {
+            self.texture.set_options(device, sampling);
+            self.diffuse_bind_group = Self::create_diffuse_bind_group(
+                device,
+                &self.texture_bind_group_layout,
+                &self.texture,
+            );
+        }
+        self.uv_mapping = uv_mapping;
+        self.shading.letterbox = (uv_mapping == UvMapping::Fit(FitMode::Contain)) as u32;
+        self.write_shading(queue);
+        self.remap_tex_coords(queue);
+    }
+
+    /// Swaps the image printed on the cloth for one decoded from `bytes`
+    /// (PNG or JPEG). UVs are recomputed for the new aspect ratio with the
+    /// current `UvMapping`, so a fit mode still keeps the whole image visible.
+    pub fn set_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let texture = Texture::from_bytes(
+            device,
+            queue,
+            bytes,
+            "cloth texture",
+            self.uv_mapping.texture_options(self.texture_options),
+        )?;
+        self.texture = texture;
+        self.image_bytes = bytes.to_vec();
+        self.diffuse_bind_group =
+            Self::create_diffuse_bind_group(device, &self.texture_bind_group_layout, &self.texture);
+        self.remap_tex_coords(queue);
+        Ok(())
+    }
+
+    fn remap_tex_coords(&mut self, queue: &wgpu::Queue) {
+        Self::map_tex_coords(
+            &mut self.sim.particles,
+            self.uv_mapping,
+            self.sim.width / self.sim.height,
+            self.texture.aspect(),
+            self.sim.num_particles_width,
+            self.sim.num_particles_height,
+        );
+        let mut tex_coord = vec![];
+        Self::fill_tex_coords(&self.particles, &mut tex_coord);
+        queue.write_buffer(&self.tex_coord_buffer, 0, bytemuck::cast_slice(&tex_coord));
+    }
+
+    /// `bind_group_layouts` are the camera, texture, shading and environment
+    /// groups, in that order.
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> (
+        wgpu::ShaderModule,
+        wgpu::PipelineLayout,
+        wgpu::RenderPipeline,
+    ) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particle.wgsl").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle pipeline layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            format,
+            sample_count,
+            DepthConfig::default().compare,
+            wgpu::PolygonMode::Fill,
+        );
+
+        (shader, pipeline_layout, pipeline)
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        vertices: &[Vertex],
+        normals: &[Vertex],
+        tex_coord: &[Vertex2],
+        indices: &[u32],
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer, wgpu::Buffer) {
+        // The GPU solver writes positions and normals straight into these
+        let vertex_usage = wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        #[cfg(feature = "compute")]
+        let vertex_usage = vertex_usage | wgpu::BufferUsages::STORAGE;
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: vertex_usage,
+        });
+        let vertex_normal_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: "Vertex Normal Buffer".into(),
+            contents: bytemuck::cast_slice(normals),
+            usage: vertex_usage,
+        });
+        let tex_coord_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: "Texture Coord Buffer".into(),
+            contents: bytemuck::cast_slice(tex_coord),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        (
+            vertex_buffer,
+            vertex_normal_buffer,
+            tex_coord_buffer,
+            index_buffer,
+        )
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_compare: wgpu::CompareFunction,
+        polygon_mode: wgpu::PolygonMode,
+    ) -> wgpu::RenderPipeline {
+        // Mark every covered pixel so the outline can be drawn around it
+        let stencil_face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle render pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), Self::normal_desc(), Self::tex_coord_desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                // cull_mode: Some(wgpu::Face::Back),
+                cull_mode: None,
+                polygon_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState {
+                    front: stencil_face,
+                    back: stencil_face,
+                    read_mask: 0xff,
+                    write_mask: 0xff,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    /// Rebuilds the render pipeline to match a new MSAA sample count.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+        self.outline.set_sample_count(device, sample_count);
+        self.rebuild_pipelines(device);
+    }
+
+    /// Rebuilds the render pipeline with a new depth test, e.g. `GreaterEqual`
+    /// for a reverse-Z projection.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        if depth_compare == self.depth_compare {
+            return;
+        }
+        self.depth_compare = depth_compare;
+        self.rebuild_pipelines(device);
+    }
+
+    fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.format,
+            self.sample_count,
+            self.depth_compare,
+            wgpu::PolygonMode::Fill,
+        );
+        if self.wireframe_pipeline.is_some() {
+            self.wireframe_pipeline = Some(Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.shader,
+                self.format,
+                self.sample_count,
+                self.depth_compare,
+                wgpu::PolygonMode::Line,
+            ));
+        }
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe_pipeline.is_some()
+    }
+
+    /// Draws just the edges of the mesh's triangles, for seeing the grid the
+    /// constraints run along. Needs `Features::POLYGON_MODE_LINE`, which
+    /// WebGL doesn't have; without it the cloth stays solid.
+    pub fn set_wireframe(&mut self, device: &wgpu::Device, wireframe: bool) {
+        if !wireframe {
+            self.wireframe_pipeline = None;
+            return;
+        }
+        if !device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            log::warn!("Wireframe needs POLYGON_MODE_LINE, which this device doesn't support");
+            return;
+        }
+        if self.wireframe_pipeline.is_none() {
+            self.wireframe_pipeline = Some(Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.shader,
+                self.format,
+                self.sample_count,
+                self.depth_compare,
+                wgpu::PolygonMode::Line,
+            ));
+        }
+    }
+
+    /// One vertex and normal per particle, row by row, drawn with the
+    /// triangles from `fill_indices`.
+    fn fill_vertices(
+        particles: &[Particle],
+        vertices: &mut Vec<Vertex>,
+        normals: &mut Vec<Vertex>,
+    ) {
+        vertices.clear();
+        normals.clear();
+
+        vertices.extend(particles.iter().map(|p| Vertex {
+            position: p.position.into(),
+            // _pad: 0.0,
+        }));
+
+        normals.extend(particles.iter().map(|p| Vertex {
+            position: p.accumulated_normal.normalize().into(),
+            // _pad: 0.0,
+        }));
+    }
+
+    /// The UVs for every vertex `fill_vertices` writes. They only depend on
+    /// the UV mapping, so unlike positions they're filled once and again only
+    /// when the mapping changes.
+    fn fill_tex_coords(particles: &[Particle], tex_coord: &mut Vec<Vertex2>) {
+        tex_coord.clear();
+        tex_coord.extend(particles.iter().map(|p| Vertex2 {
+            position: p.tex_coords.into(),
+        }));
+    }
+
+    /// Two triangles per grid square, 6 indices each. Torn triangles are
+    /// collapsed onto a single point rather than left out, so the index
+    /// buffer keeps its size and every square keeps its slot.
+    fn fill_indices(
+        edges: &EdgeSet,
+        indices: &mut Vec<u32>,
+        num_particles_width: usize,
+        num_particles_height: usize,
+    ) {
+        indices.clear();
+
+        let get_particle_idx = |x: usize, y: usize| -> u32 { (y * num_particles_width + x) as u32 };
+
+        for x in 0..num_particles_width - 1 {
+            for y in 0..num_particles_height - 1 {
+                let mut tmp = [
+                    get_particle_idx(x + 1, y),
+                    get_particle_idx(x, y),
+                    get_particle_idx(x, y + 1),
+                    //
+                    get_particle_idx(x + 1, y + 1),
+                    get_particle_idx(x + 1, y),
+                    get_particle_idx(x, y + 1),
+                ];
+                for (i, alive) in edges.triangles_alive(x, y).into_iter().enumerate() {
+                    if !alive {
+                        tmp[i * 3 + 1] = tmp[i * 3];
+                        tmp[i * 3 + 2] = tmp[i * 3];
+                    }
+                }
+                indices.extend(tmp);
+            }
+        }
+    }
+
+    pub fn render<'a, 'b>(
+        &'a self,
+        camera_bind_group: &'a wgpu::BindGroup,
+        render_pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        render_pass.set_pipeline(self.wireframe_pipeline.as_ref().unwrap_or(&self.pipeline));
+        render_pass.set_stencil_reference(OUTLINE_STENCIL_REF);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.diffuse_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.shading_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.environment_bind_group, &[]);
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.vertex_normal_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.tex_coord_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        render_pass.draw_indexed(0..self.index_count(), 0, 0..1);
+
+        self.outline.render(
+            camera_bind_group,
+            &self.vertex_buffer,
+            &self.index_buffer,
+            self.index_count(),
+            render_pass,
+        );
+    }
+
+    pub fn update_wgpu(&mut self, queue: &wgpu::Queue) {
+        Self::fill_vertices(&self.sim.particles, &mut self.vertices, &mut self.normals);
+        self.upload_vertices(queue);
+    }
+
+    /// Like `update_wgpu`, but takes the positions and normals the simulation
+    /// thread published instead of rebuilding them. The cloth's previous
+    /// buffers are swapped into `snapshot` for the thread to reuse. A
+    /// snapshot from before the grid was rebuilt is ignored.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn upload_snapshot(&mut self, queue: &wgpu::Queue, snapshot: &mut ClothSnapshot) {
+        if snapshot.vertices.len() == self.particles.len() {
+            std::mem::swap(&mut self.vertices, &mut snapshot.vertices);
+            std::mem::swap(&mut self.normals, &mut snapshot.normals);
+        } else {
+            Self::fill_vertices(&self.sim.particles, &mut self.vertices, &mut self.normals);
+        }
+        self.upload_vertices(queue);
+    }
+
+    fn upload_vertices(&mut self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        if self.indices_dirty {
+            Self::fill_indices(
+                &self.sim.edges,
+                &mut self.indices,
+                self.sim.num_particles_width,
+                self.sim.num_particles_height,
+            );
+            queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&self.indices));
+            self.indices_dirty = false;
+        }
+        queue.write_buffer(
+            &self.vertex_normal_buffer,
+            0,
+            bytemuck::cast_slice(&self.normals),
+        );
+
+        #[cfg(feature = "compute")]
+        if let Some(solver) = self.gpu_solver.as_ref() {
+            solver.upload_particles(queue, &self.particles);
+        }
+
+        if self.outline.enabled() {
+            let center = self.center();
+            self.outline.set_center(queue, center);
+        }
+    }
+
+    /// Moves stepping onto the GPU, see `ComputeSolver`. The current
+    /// particles and constraints are uploaded once; changes made on the CPU
+    /// afterwards only reach the GPU through `update_wgpu`, and stiffness
+    /// changes need the solver re-enabled.
+    #[cfg(feature = "compute")]
+    pub fn enable_gpu_solver(&mut self, device: &wgpu::Device) {
+        self.gpu_solver = Some(ComputeSolver::new(
+            device,
+            &self.particles,
+            &self.constraints,
+            self.num_particles_width,
+            self.num_particles_height,
+            &self.vertex_buffer,
+            &self.vertex_normal_buffer,
+        ));
+    }
+
+    /// Brings the GPU's particles back and returns to stepping on the CPU.
+    #[cfg(feature = "compute")]
+    pub fn disable_gpu_solver(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.sync_from_gpu(device, queue);
+        self.gpu_solver = None;
+    }
+
+    #[cfg(feature = "compute")]
+    pub fn gpu_solver_enabled(&self) -> bool {
+        self.gpu_solver.is_some()
+    }
+
+    /// Runs `steps` steps on the GPU, or on the CPU if the GPU solver is off.
+    #[cfg(feature = "compute")]
+    pub fn step_gpu(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        steps: usize,
+        timestep: f32,
+    ) {
+        let wind_weight = self.wind_weight();
+        let solver = match self.gpu_solver.as_mut() {
+            Some(solver) => solver,
+            None => {
+                for _ in 0..steps {
+                    self.update(timestep);
+                }
+                self.update_normals();
+                self.update_wgpu(queue);
+                return;
+            }
+        };
+
+        for _ in 0..steps {
+            solver.step(
+                device,
+                queue,
+                &ComputeParams {
+                    gravity: self.sim.gravity,
+                    wind: self.sim.wind.at(self.sim.sim_time),
+                    wind_weight,
+                    wind_front_faces_only: self.sim.wind_front_faces_only,
+                    timestep,
+                    damping: self.sim.damping,
+                    max_velocity: self.sim.max_velocity,
+                    constraint_iterations: self.sim.constraint_iterations,
+                },
+            );
+            self.sim.sim_time += timestep;
+        }
+    }
+
+    /// Copies the GPU solver's particles back so CPU queries like
+    /// `intersects` and `particles` see them. Blocks until the GPU catches
+    /// up; does nothing if the GPU solver is off.
+    #[cfg(feature = "compute")]
+    pub fn sync_from_gpu(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(solver) = self.gpu_solver.as_ref() {
+            solver.read_particles(device, queue, &mut self.sim.particles);
+            self.update_normals();
+        }
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &wgpu::Buffer {
+        &self.index_buffer
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertices.len() as u32
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.indices.len() as u32
+    }
+}
+
+fn vec_to_point(vec: &Vector3<f32>) -> Point3<f32> {
+    Point3::new(vec.x, vec.y, vec.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small sheet hanging still, with no wind.
+    fn small_cloth() -> ClothSimulation {
+        ClothBuilder::new()
+            .width(4.0)
+            .height(3.0)
+            .particles_x(10)
+            .particles_y(8)
+            .wind(vec3(0.0, 0.0, 0.0))
+            .build_simulation()
+    }
+
+    fn kinetic_energy(sim: &ClothSimulation) -> f32 {
+        sim.particles()
+            .iter()
+            .filter(|p| p.is_movable)
+            .map(|p| 0.5 * (p.position - p.old_position).magnitude2() / p.inv_mass)
+            .sum()
+    }
+
+    /// Sets every particle moving toward the camera.
+    fn push(sim: &mut ClothSimulation) {
+        for particle in sim.particles.iter_mut() {
+            particle.old_position -= vec3(0.0, 0.0, 0.05);
+        }
+    }
+
+    #[test]
+    fn kinetic_energy_decays() {
+        let mut sim = small_cloth();
+        push(&mut sim);
+        let start = kinetic_energy(&sim);
+        for _ in 0..600 {
+            sim.update(TIME_STEP);
+        }
+        let end = kinetic_energy(&sim);
+        assert!(end < start * 0.1, "{} -> {}", start, end);
+    }
+
+    #[test]
+    fn higher_damping_settles_sooner() {
+        let settle = |damping: f32| {
+            let mut sim = small_cloth();
+            sim.set_damping(damping);
+            push(&mut sim);
+            for _ in 0..60 {
+                sim.update(TIME_STEP);
+            }
+            kinetic_energy(&sim)
+        };
+        let (low, high) = (settle(DAMPING), settle(0.1));
+        assert!(high < low, "{} with more damping, {} with less", high, low);
+    }
+
+    #[test]
+    fn bounding_box_of_a_fresh_grid() {
+        let (min, max) = small_cloth().bounding_box();
+        // The grid starts at the origin and steps width / particles_x across
+        // and height / particles_y down, stopping one step short of the edge
+        assert_eq!(min, vec3(0.0, -3.0 * (7.0 / 8.0), 0.0));
+        assert_eq!(max, vec3(4.0 * (9.0 / 10.0), 0.0, 0.0));
+    }
+
+    #[test]
+    fn sanitize_reset_keeps_tex_coords() {
+        let mut sim = small_cloth();
+        sim.set_safe_mode(true);
+        // Stand-ins for whatever `UvMapping` the `Cloth` mapped
+        for (i, particle) in sim.particles.iter_mut().enumerate() {
+            particle.tex_coords = vec2(i as f32, 0.5);
+        }
+        for particle in sim.particles.iter_mut() {
+            particle.position = vec3(f32::NAN, f32::NAN, f32::NAN);
+            particle.old_position = vec3(f32::NAN, f32::NAN, f32::NAN);
+        }
+
+        sim.update(TIME_STEP);
+
+        for (i, particle) in sim.particles().iter().enumerate() {
+            assert!(
+                particle.position.x.is_finite(),
+                "Particle {} wasn't reset",
+                i
+            );
+            assert_eq!(particle.tex_coords, vec2(i as f32, 0.5));
+        }
+    }
+
+    #[test]
+    fn coincident_particles_stay_put() {
+        let particle = Particle {
+            position: vec3(1.0, 2.0, 3.0),
+            old_position: vec3(1.0, 2.0, 3.0),
+            ..Default::default()
+        };
+        let mut particles = vec![particle.clone(), particle];
+        let constraint = Constraint::new(0, 1, 1.0, ConstraintKind::Structural);
+
+        let (offset_p1, offset_p2) = constraint.correction(&particles);
+        assert_eq!(offset_p1, vec3(0.0, 0.0, 0.0));
+        assert_eq!(offset_p2, vec3(0.0, 0.0, 0.0));
+
+        constraint.satisfy(&mut particles);
+        for particle in &particles {
+            assert_eq!(particle.position, vec3(1.0, 2.0, 3.0));
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_solver_settles_like_the_serial_one() {
+        // Hung from the whole top edge the sheet is pulled taut everywhere,
+        // so there's one rest state rather than a crumpled top row that
+        // could settle either way
+        let builder = ClothBuilder::new()
+            .width(4.0)
+            .height(3.0)
+            .particles_x(10)
+            .particles_y(8)
+            .constraint_iterations(20)
+            .wind(vec3(0.0, 0.0, 0.0))
+            .pin_layout(PinLayout::TopEdge);
+        let mut parallel = builder.build_simulation();
+        // One color per constraint, in the order the grid built them, solves
+        // them one at a time just like the serial solver
+        let mut serial = builder.build_simulation();
+        let (_, constraints) = ClothSimulation::create_grid(4.0, 3.0, 10, 8, PinLayout::TopEdge);
+        serial.constraint_colors = (0..constraints.len()).map(|i| i..i + 1).collect();
+        serial.constraints = constraints;
+
+        for _ in 0..600 {
+            parallel.update(TIME_STEP);
+            serial.update(TIME_STEP);
+        }
+        let furthest = parallel
+            .particles()
+            .iter()
+            .zip(serial.particles())
+            .map(|(p, s)| (p.position - s.position).magnitude())
+            .fold(0.0, f32::max);
+        assert!(furthest < 0.01, "Particles ended up {} apart", furthest);
+    }
 }
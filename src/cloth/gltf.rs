@@ -0,0 +1,135 @@
+use anyhow::Result;
+
+use super::Cloth;
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+impl Cloth {
+    /// Writes the cloth as it is right now to a binary glTF (`.glb`) file,
+    /// see `to_glb`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_glb(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        std::fs::write(path, self.to_glb()?)?;
+        Ok(())
+    }
+
+    /// The cloth as a binary glTF: one mesh with positions, normals and UVs,
+    /// and the image printed on it embedded as its material's base color.
+    /// Torn triangles are left out.
+    pub fn to_glb(&self) -> Result<Vec<u8>> {
+        let mut vertices = vec![];
+        let mut normals = vec![];
+        let mut tex_coord = vec![];
+        let mut indices = vec![];
+        Self::fill_vertices(&self.particles, &mut vertices, &mut normals);
+        Self::fill_tex_coords(&self.particles, &mut tex_coord);
+        Self::fill_indices(
+            &self.edges,
+            &mut indices,
+            self.num_particles_width,
+            self.num_particles_height,
+        );
+        // Collapsed triangles only exist to keep the GPU buffer's layout
+        let indices: Vec<u32> = indices
+            .chunks(3)
+            .filter(|triangle| triangle[0] != triangle[1])
+            .flatten()
+            .copied()
+            .collect();
+
+        let mime_type = match image::guess_format(&self.image_bytes)? {
+            image::ImageFormat::Png => "image/png",
+            image::ImageFormat::Jpeg => "image/jpeg",
+            format => anyhow::bail!("glTF can't embed {:?} images", format),
+        };
+
+        // Every view starts 4-byte aligned, as glTF requires
+        let mut bin = vec![];
+        let mut views = vec![];
+        let mut push_view = |bytes: &[u8], target: Option<u32>| {
+            let offset = bin.len();
+            bin.extend_from_slice(bytes);
+            while bin.len() % 4 != 0 {
+                bin.push(0);
+            }
+            let target = target
+                .map(|target| format!(r#","target":{}"#, target))
+                .unwrap_or_default();
+            views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{}{}}}"#,
+                offset,
+                bytes.len(),
+                target
+            ));
+        };
+        push_view(bytemuck::cast_slice(&vertices), Some(ARRAY_BUFFER));
+        push_view(bytemuck::cast_slice(&normals), Some(ARRAY_BUFFER));
+        push_view(bytemuck::cast_slice(&tex_coord), Some(ARRAY_BUFFER));
+        push_view(bytemuck::cast_slice(&indices), Some(ELEMENT_ARRAY_BUFFER));
+        push_view(&self.image_bytes, None);
+
+        let (min, max) = self.bounding_box();
+        let json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"tp-twitter"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#,
+                r#""meshes":[{{"primitives":[{{"#,
+                r#""attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"#,
+                r#""indices":3,"material":0}}]}}],"#,
+                r#""materials":[{{"doubleSided":true,"pbrMetallicRoughness":{{"#,
+                r#""baseColorTexture":{{"index":0}},"metallicFactor":0.0}}}}],"#,
+                r#""textures":[{{"source":0,"sampler":0}}],"samplers":[{{}}],"#,
+                r#""images":[{{"bufferView":4,"mimeType":"{}"}}],"#,
+                r#""accessors":["#,
+                r#"{{"bufferView":0,"componentType":{},"count":{},"type":"VEC3","#,
+                r#""min":[{},{},{}],"max":[{},{},{}]}},"#,
+                r#"{{"bufferView":1,"componentType":{},"count":{},"type":"VEC3"}},"#,
+                r#"{{"bufferView":2,"componentType":{},"count":{},"type":"VEC2"}},"#,
+                r#"{{"bufferView":3,"componentType":{},"count":{},"type":"SCALAR"}}],"#,
+                r#""bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            ),
+            mime_type,
+            FLOAT,
+            vertices.len(),
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z,
+            FLOAT,
+            normals.len(),
+            FLOAT,
+            tex_coord.len(),
+            UNSIGNED_INT,
+            indices.len(),
+            views.join(","),
+            bin.len(),
+        );
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let total_len = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(total_len);
+        for word in [GLB_MAGIC, GLB_VERSION, total_len as u32] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+        Ok(glb)
+    }
+}
@@ -0,0 +1,201 @@
+struct Params {
+    gravity: vec4<f32>,
+    wind: vec4<f32>,
+    timestep: f32,
+    damping: f32,
+    max_velocity: f32,
+    num_particles_width: u32,
+    num_particles_height: u32,
+    wind_weight: f32,
+    wind_front_faces_only: u32,
+}
+
+// `position.w` holds the inverse mass, 0 for pinned particles
+struct Particle {
+    position: vec4<f32>,
+    old_position: vec4<f32>,
+}
+
+// One end of a constraint, seen from the particle at the other end
+struct Link {
+    other: u32,
+    rest_distance: f32,
+    stiffness: f32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> particles_in: array<Particle>;
+@group(0) @binding(2) var<storage, read_write> particles_out: array<Particle>;
+@group(0) @binding(3) var<storage, read> link_offsets: array<u32>;
+@group(0) @binding(4) var<storage, read> links: array<Link>;
+@group(0) @binding(5) var<storage, read_write> vertices: array<f32>;
+@group(0) @binding(6) var<storage, read_write> vertex_normals: array<f32>;
+
+fn particle_idx(x: i32, y: i32) -> u32 {
+    return u32(y) * params.num_particles_width + u32(x);
+}
+
+fn num_particles() -> u32 {
+    return params.num_particles_width * params.num_particles_height;
+}
+
+// The `k`th of the (up to) six triangles touching particle `(x, y)`, laid out
+// like `Cloth::fill_indices`. `w` is 0 if the triangle is off the grid.
+fn adjacent_triangle(x: i32, y: i32, k: u32) -> vec4<i32> {
+    var sx = x;
+    var sy = y;
+    var second = false;
+    switch (k) {
+        case 0u: { sx = x - 1; }
+        case 1u: { }
+        case 2u: { sy = y - 1; }
+        case 3u: { sx = x - 1; sy = y - 1; second = true; }
+        case 4u: { sx = x - 1; second = true; }
+        default: { sy = y - 1; second = true; }
+    }
+    let w = i32(params.num_particles_width);
+    let h = i32(params.num_particles_height);
+    if (sx < 0 || sy < 0 || sx >= w - 1 || sy >= h - 1) {
+        return vec4<i32>(0, 0, 0, 0);
+    }
+    if (second) {
+        return vec4<i32>(
+            i32(particle_idx(sx + 1, sy + 1)),
+            i32(particle_idx(sx + 1, sy)),
+            i32(particle_idx(sx, sy + 1)),
+            1
+        );
+    }
+    return vec4<i32>(
+        i32(particle_idx(sx + 1, sy)),
+        i32(particle_idx(sx, sy)),
+        i32(particle_idx(sx, sy + 1)),
+        1
+    );
+}
+
+fn triangle_normal(t: vec4<i32>) -> vec3<f32> {
+    let p1 = particles_in[t.x].position.xyz;
+    let p2 = particles_in[t.y].position.xyz;
+    let p3 = particles_in[t.z].position.xyz;
+    return cross(p2 - p1, p3 - p1);
+}
+
+@compute @workgroup_size(64)
+fn integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= num_particles()) {
+        return;
+    }
+    var p = particles_in[i];
+    if (p.position.w == 0.0) {
+        particles_out[i] = p;
+        return;
+    }
+
+    // Same wind as `Cloth::add_wind_force`, summed over the triangles around
+    // this particle
+    let x = i32(i % params.num_particles_width);
+    let y = i32(i / params.num_particles_width);
+    var wind = vec3<f32>(0.0, 0.0, 0.0);
+    for (var k = 0u; k < 6u; k += 1u) {
+        let t = adjacent_triangle(x, y, k);
+        if (t.w == 0) {
+            continue;
+        }
+        let normal = triangle_normal(t);
+        let len = length(normal);
+        if (len > 0.0) {
+            let n = normal / len;
+            let pressure = dot(n, params.wind.xyz);
+            if (params.wind_front_faces_only == 0u || pressure <= 0.0) {
+                wind += n * pressure * params.wind_weight;
+            }
+        }
+    }
+    let acceleration = (params.gravity.xyz + wind) * params.timestep;
+
+    var velocity = p.position.xyz - p.old_position.xyz;
+    let speed = length(velocity);
+    if (speed > params.max_velocity) {
+        velocity *= params.max_velocity / speed;
+    }
+    let position = p.position.xyz
+        + velocity * (1.0 - params.damping)
+        + acceleration * params.timestep;
+
+    particles_out[i] = Particle(vec4<f32>(position, p.position.w), vec4<f32>(p.position.xyz, 0.0));
+}
+
+// One Jacobi pass: every particle moves by the average of the corrections its
+// constraints ask for, reading only last pass's positions
+@compute @workgroup_size(64)
+fn solve(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= num_particles()) {
+        return;
+    }
+    let p = particles_in[i];
+    let inv_mass = p.position.w;
+    if (inv_mass == 0.0) {
+        particles_out[i] = p;
+        return;
+    }
+
+    let start = link_offsets[i];
+    let end = link_offsets[i + 1u];
+    var correction = vec3<f32>(0.0, 0.0, 0.0);
+    for (var l = start; l < end; l += 1u) {
+        let link = links[l];
+        let other = particles_in[link.other].position;
+        let to_other = other.xyz - p.position.xyz;
+        let dist = length(to_other);
+        let total_inv_mass = inv_mass + other.w;
+        // Same cutoff as MIN_CONSTRAINT_DISTANCE in cloth.rs
+        if (dist >= 1e-6) {
+            correction += to_other * (1.0 - link.rest_distance / dist)
+                * link.stiffness * (inv_mass / total_inv_mass);
+        }
+    }
+    if (end > start) {
+        correction /= f32(end - start);
+    }
+
+    particles_out[i] = Particle(vec4<f32>(p.position.xyz + correction, inv_mass), p.old_position);
+}
+
+fn particle_normal(particle: i32) -> vec3<f32> {
+    let x = particle % i32(params.num_particles_width);
+    let y = particle / i32(params.num_particles_width);
+    var normal = vec3<f32>(0.0, 0.0, 0.0);
+    for (var k = 0u; k < 6u; k += 1u) {
+        let t = adjacent_triangle(x, y, k);
+        if (t.w == 0) {
+            continue;
+        }
+        let n = triangle_normal(t);
+        if (length(n) > 0.0) {
+            normal += normalize(n);
+        }
+    }
+    return normalize(normal);
+}
+
+// Writes the render vertex buffers straight from the particles, one vertex
+// per particle like `Cloth::fill_vertices`
+@compute @workgroup_size(64)
+fn write_vertices(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= num_particles()) {
+        return;
+    }
+    let pos = particles_in[i].position.xyz;
+    let normal = particle_normal(i32(i));
+    vertices[i * 3u] = pos.x;
+    vertices[i * 3u + 1u] = pos.y;
+    vertices[i * 3u + 2u] = pos.z;
+    vertex_normals[i * 3u] = normal.x;
+    vertex_normals[i * 3u + 1u] = normal.y;
+    vertex_normals[i * 3u + 2u] = normal.z;
+}
@@ -0,0 +1,381 @@
+use std::sync::mpsc;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::cloth::{Constraint, Particle};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GpuParticle {
+    /// `w` is the inverse mass, 0 for pinned particles.
+    position: [f32; 4],
+    old_position: [f32; 4],
+}
+
+impl GpuParticle {
+    fn new(particle: &Particle) -> Self {
+        Self {
+            position: particle
+                .position
+                .extend(particle.effective_inv_mass())
+                .into(),
+            old_position: particle.old_position.extend(0.0).into(),
+        }
+    }
+}
+
+/// One end of a constraint. Every constraint is stored twice, once under
+/// each of its particles, so a particle only ever reads its own links.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GpuLink {
+    other: u32,
+    rest_distance: f32,
+    stiffness: f32,
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ComputeUniform {
+    gravity: [f32; 4],
+    wind: [f32; 4],
+    timestep: f32,
+    damping: f32,
+    max_velocity: f32,
+    num_particles_width: u32,
+    num_particles_height: u32,
+    wind_weight: f32,
+    wind_front_faces_only: u32,
+    _pad: u32,
+}
+
+/// The per-step inputs to `ComputeSolver::step`, the same ones
+/// `Cloth::update` uses on the CPU.
+pub struct ComputeParams {
+    pub gravity: Vector3<f32>,
+    pub wind: Vector3<f32>,
+    /// See `Cloth::add_wind_force`.
+    pub wind_weight: f32,
+    pub wind_front_faces_only: bool,
+    pub timestep: f32,
+    pub damping: f32,
+    pub max_velocity: f32,
+    pub constraint_iterations: usize,
+}
+
+/// Steps the cloth in a compute shader and writes the result straight into
+/// its vertex buffers, so nothing is copied back to the CPU unless asked.
+///
+/// Constraints are relaxed with Jacobi passes rather than the CPU's in-place
+/// Gauss-Seidel ones, so the sheet is a little stretchier for the same number
+/// of iterations. Tearing, colliders and the floor are CPU only.
+pub struct ComputeSolver {
+    integrate_pipeline: wgpu::ComputePipeline,
+    solve_pipeline: wgpu::ComputePipeline,
+    write_vertices_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    particle_buffers: [wgpu::Buffer; 2],
+    /// `bind_groups[i]` reads `particle_buffers[i]` and writes the other.
+    bind_groups: [wgpu::BindGroup; 2],
+    readback_buffer: wgpu::Buffer,
+    /// Which particle buffer holds the latest positions.
+    current: usize,
+    num_particles_width: usize,
+    num_particles_height: usize,
+}
+
+impl ComputeSolver {
+    pub fn new(
+        device: &wgpu::Device,
+        particles: &[Particle],
+        constraints: &[Constraint],
+        num_particles_width: usize,
+        num_particles_height: usize,
+        vertex_buffer: &wgpu::Buffer,
+        vertex_normal_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cloth compute shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("cloth_compute.wgsl").into()),
+        });
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cloth compute bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, true),
+                storage_entry(4, true),
+                storage_entry(5, false),
+                storage_entry(6, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cloth compute pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let integrate_pipeline = create_pipeline("integrate");
+        let solve_pipeline = create_pipeline("solve");
+        let write_vertices_pipeline = create_pipeline("write_vertices");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cloth compute uniform buffer"),
+            size: std::mem::size_of::<ComputeUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let gpu_particles: Vec<GpuParticle> = particles.iter().map(GpuParticle::new).collect();
+        let create_particle_buffer = |label: &str| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&gpu_particles),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+            })
+        };
+        let particle_buffers = [
+            create_particle_buffer("Cloth particle buffer A"),
+            create_particle_buffer("Cloth particle buffer B"),
+        ];
+
+        let (offsets, links) = Self::build_links(particles.len(), constraints);
+        let offset_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cloth link offset buffer"),
+            contents: bytemuck::cast_slice(&offsets),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let link_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cloth link buffer"),
+            contents: bytemuck::cast_slice(&links),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let create_bind_group = |input: &wgpu::Buffer, output: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Cloth compute bind group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: input.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: output.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: offset_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: link_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: vertex_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: vertex_normal_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let bind_groups = [
+            create_bind_group(&particle_buffers[0], &particle_buffers[1]),
+            create_bind_group(&particle_buffers[1], &particle_buffers[0]),
+        ];
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cloth particle readback buffer"),
+            size: (gpu_particles.len() * std::mem::size_of::<GpuParticle>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            integrate_pipeline,
+            solve_pipeline,
+            write_vertices_pipeline,
+            uniform_buffer,
+            particle_buffers,
+            bind_groups,
+            readback_buffer,
+            current: 0,
+            num_particles_width,
+            num_particles_height,
+        }
+    }
+
+    /// Groups the constraints by particle: particle `i`'s links are
+    /// `links[offsets[i]..offsets[i + 1]]`.
+    fn build_links(num_particles: usize, constraints: &[Constraint]) -> (Vec<u32>, Vec<GpuLink>) {
+        let mut per_particle = vec![vec![]; num_particles];
+        for constraint in constraints {
+            let link = |other: usize| GpuLink {
+                other: other as u32,
+                rest_distance: constraint.rest_distance,
+                stiffness: constraint.stiffness,
+                _pad: 0,
+            };
+            per_particle[constraint.p1].push(link(constraint.p2));
+            per_particle[constraint.p2].push(link(constraint.p1));
+        }
+
+        let mut offsets = Vec::with_capacity(num_particles + 1);
+        let mut links = vec![];
+        for particle_links in per_particle {
+            offsets.push(links.len() as u32);
+            links.extend(particle_links);
+        }
+        offsets.push(links.len() as u32);
+        (offsets, links)
+    }
+
+    /// Replaces the GPU's particles with the CPU's, e.g. after a reset.
+    pub fn upload_particles(&self, queue: &wgpu::Queue, particles: &[Particle]) {
+        let gpu_particles: Vec<GpuParticle> = particles.iter().map(GpuParticle::new).collect();
+        queue.write_buffer(
+            &self.particle_buffers[self.current],
+            0,
+            bytemuck::cast_slice(&gpu_particles),
+        );
+    }
+
+    /// Runs one simulation step and rewrites the vertex buffers.
+    pub fn step(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, params: &ComputeParams) {
+        let uniform = ComputeUniform {
+            gravity: params.gravity.extend(0.0).into(),
+            wind: params.wind.extend(0.0).into(),
+            timestep: params.timestep,
+            damping: params.damping,
+            max_velocity: params.max_velocity,
+            num_particles_width: self.num_particles_width as u32,
+            num_particles_height: self.num_particles_height as u32,
+            wind_weight: params.wind_weight,
+            wind_front_faces_only: params.wind_front_faces_only as u32,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let num_particles = (self.num_particles_width * self.num_particles_height) as u32;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cloth compute encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Cloth compute pass"),
+            });
+
+            pass.set_pipeline(&self.integrate_pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            pass.dispatch_workgroups(workgroups(num_particles), 1, 1);
+            self.current ^= 1;
+
+            pass.set_pipeline(&self.solve_pipeline);
+            for _ in 0..params.constraint_iterations {
+                pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+                pass.dispatch_workgroups(workgroups(num_particles), 1, 1);
+                self.current ^= 1;
+            }
+
+            pass.set_pipeline(&self.write_vertices_pipeline);
+            pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            pass.dispatch_workgroups(workgroups(num_particles), 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Copies the GPU's positions back into `particles`, blocking until the
+    /// GPU is done. Only needed before reading the particles on the CPU,
+    /// e.g. for `Cloth::intersects`.
+    pub fn read_particles(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &mut [Particle],
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cloth readback encoder"),
+        });
+        encoder.copy_buffer_to_buffer(
+            &self.particle_buffers[self.current],
+            0,
+            &self.readback_buffer,
+            0,
+            self.readback_buffer.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        if !matches!(rx.recv(), Ok(Ok(()))) {
+            log::warn!("Failed to read back cloth particles");
+            return;
+        }
+
+        {
+            let data = slice.get_mapped_range();
+            let gpu_particles: &[GpuParticle] = bytemuck::cast_slice(&data);
+            for (particle, gpu) in particles.iter_mut().zip(gpu_particles) {
+                particle.position = Vector3::new(gpu.position[0], gpu.position[1], gpu.position[2]);
+                particle.old_position = Vector3::new(
+                    gpu.old_position[0],
+                    gpu.old_position[1],
+                    gpu.old_position[2],
+                );
+            }
+        }
+        self.readback_buffer.unmap();
+    }
+}
+
+fn workgroups(invocations: u32) -> u32 {
+    invocations.div_ceil(WORKGROUP_SIZE)
+}
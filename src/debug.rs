@@ -1,6 +1,11 @@
+use cgmath::InnerSpace;
 use wgpu::{util::DeviceExt, ShaderStages};
 
+use crate::{cloth::Particle, texture::Texture, Vertex};
+
 const DEFAULT_INSTANCE_BUFFER_CAP: usize = 1024;
+/// How long the lines drawn by `NormalLines` are, in world units.
+const NORMAL_LINE_LENGTH: f32 = 0.2;
 
 // #[derive(Debug)]
 // #[repr(C)]
@@ -67,3 +72,181 @@ impl Debug {
         }
     }
 }
+
+/// Draws every particle's normal as a short line, blue at the particle and
+/// yellow at the tip, for checking which way the triangles face.
+pub struct NormalLines {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    depth_compare: wgpu::CompareFunction,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: Option<wgpu::Buffer>,
+    vertices: Vec<Vertex>,
+    pub enabled: bool,
+}
+
+impl NormalLines {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_compare: wgpu::CompareFunction,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Normal lines shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("normals.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Normal lines pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            format,
+            sample_count,
+            depth_compare,
+        );
+
+        Self {
+            shader,
+            pipeline_layout,
+            format,
+            sample_count,
+            depth_compare,
+            pipeline,
+            vertex_buffer: None,
+            vertices: vec![],
+            enabled: false,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_compare: wgpu::CompareFunction,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Normal lines render pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    fn rebuild_pipeline(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.format,
+            self.sample_count,
+            self.depth_compare,
+        );
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.rebuild_pipeline(device);
+        }
+    }
+
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        if depth_compare != self.depth_compare {
+            self.depth_compare = depth_compare;
+            self.rebuild_pipeline(device);
+        }
+    }
+
+    /// Rebuilds the lines from the particles' positions and normals. Does
+    /// nothing while disabled.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, particles: &[Particle]) {
+        if !self.enabled {
+            return;
+        }
+
+        self.vertices.clear();
+        for particle in particles {
+            let tip =
+                particle.position + particle.accumulated_normal.normalize() * NORMAL_LINE_LENGTH;
+            self.vertices.push(Vertex {
+                position: particle.position.into(),
+            });
+            self.vertices.push(Vertex {
+                position: tip.into(),
+            });
+        }
+
+        let size = (self.vertices.len() * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress;
+        match self.vertex_buffer.as_ref() {
+            Some(buffer) if buffer.size() == size => {
+                queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.vertices))
+            }
+            _ => {
+                self.vertex_buffer = Some(device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Normal lines vertex buffer"),
+                        contents: bytemuck::cast_slice(&self.vertices),
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    },
+                ))
+            }
+        }
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        camera_bind_group: &'a wgpu::BindGroup,
+        render_pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        let vertex_buffer = match (self.enabled, self.vertex_buffer.as_ref()) {
+            (true, Some(vertex_buffer)) => vertex_buffer,
+            _ => return,
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertices.len() as u32, 0..1);
+    }
+}
@@ -0,0 +1,29 @@
+/// Something that happened to the cloth, passed to the callback set with
+/// `State::set_event_callback`. Positions are particle grid coordinates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClothEvent {
+    /// The particle at `(x, y)` was picked up.
+    Grabbed { x: usize, y: usize },
+    /// The particle picked up at `(x, y)` was let go.
+    Released { x: usize, y: usize },
+    /// The link between the particles at `a` and `b` ripped.
+    Torn {
+        a: (usize, usize),
+        b: (usize, usize),
+    },
+}
+
+/// How one `State::update` went, passed to the callback set with
+/// `State::set_frame_stats_callback`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameStats {
+    /// Counts up from 0 with every update.
+    pub frame_index: u64,
+    /// Time since the previous update.
+    pub dt: std::time::Duration,
+    /// Fixed simulation steps taken to catch up on `dt`, see
+    /// `Physics::substeps`.
+    pub substeps: usize,
+    /// Constraint passes per step.
+    pub constraint_iterations: usize,
+}
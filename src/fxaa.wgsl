@@ -0,0 +1,69 @@
+struct Fxaa {
+    texel_size: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> fxaa: Fxaa;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let x = f32((in_vertex_index << 1u) & 2u) * 2.0 - 1.0;
+    let y = f32(in_vertex_index & 2u) * 2.0 - 1.0;
+
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+
+    return out;
+}
+
+fn sample_rgb(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// FXAA 3.11 "console" variant: find the edge direction from the luma of the
+// four diagonal neighbours and blur along it
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let reduce_min = 1.0 / 128.0;
+    let reduce_mul = 1.0 / 8.0;
+    let span_max = 8.0;
+
+    let t = fxaa.texel_size;
+    let luma = vec3<f32>(0.299, 0.587, 0.114);
+    let luma_nw = dot(sample_rgb(in.uv + vec2<f32>(-1.0, -1.0) * t), luma);
+    let luma_ne = dot(sample_rgb(in.uv + vec2<f32>(1.0, -1.0) * t), luma);
+    let luma_sw = dot(sample_rgb(in.uv + vec2<f32>(-1.0, 1.0) * t), luma);
+    let luma_se = dot(sample_rgb(in.uv + vec2<f32>(1.0, 1.0) * t), luma);
+    let luma_m = dot(sample_rgb(in.uv), luma);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-span_max, -span_max), vec2<f32>(span_max, span_max)) * t;
+
+    let rgb_a = 0.5 * (sample_rgb(in.uv + dir * (1.0 / 3.0 - 0.5)) + sample_rgb(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_rgb(in.uv - dir * 0.5) + sample_rgb(in.uv + dir * 0.5));
+    let luma_b = dot(rgb_b, luma);
+
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
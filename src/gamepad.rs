@@ -0,0 +1,108 @@
+use gilrs::{Axis, Button, EventType, Gilrs};
+use winit::event::{ElementState, MouseScrollDelta};
+
+use crate::{input::Action, main_state::State};
+
+/// Stick deflection below this counts as resting.
+const DEAD_ZONE: f32 = 0.25;
+/// How much mouse movement a fully tilted right stick is worth each frame.
+const LOOK_SPEED: f32 = 8.0;
+/// Lines scrolled each frame with a trigger all the way down.
+const ZOOM_SPEED: f32 = 0.2;
+
+/// Drives the camera and simulation from the first connected gamepad: the
+/// left stick moves like WASD, the right stick looks around like dragging
+/// the mouse, the triggers zoom, A/Cross pauses and Y/Triangle resets the
+/// cloth.
+pub struct Gamepad {
+    gilrs: Gilrs,
+    /// The movement actions the left stick is holding down.
+    held: Vec<Action>,
+}
+
+impl Gamepad {
+    /// Returns `None` if gamepads aren't supported here.
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Self {
+                gilrs,
+                held: Vec::new(),
+            }),
+            Err(err) => {
+                log::warn!("Gamepad support unavailable: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Call once a frame, before `State::update`.
+    pub fn poll(&mut self, state: &mut State) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(Button::South, _) => {
+                    let paused = !state.physics.paused();
+                    state.physics.set_paused(paused);
+                }
+                EventType::ButtonPressed(Button::North, _) => {
+                    state.physics.reset(&state.queue);
+                }
+                _ => (),
+            }
+        }
+
+        let gamepad = match self.gilrs.gamepads().next() {
+            Some((_, gamepad)) => gamepad,
+            None => return,
+        };
+        let trigger = |button| {
+            gamepad
+                .button_data(button)
+                .map(|data| data.value())
+                .unwrap_or(0.0)
+        };
+        let (move_x, move_y) = (
+            gamepad.value(Axis::LeftStickX),
+            gamepad.value(Axis::LeftStickY),
+        );
+        let (look_x, look_y) = (
+            gamepad.value(Axis::RightStickX),
+            gamepad.value(Axis::RightStickY),
+        );
+        let zoom = trigger(Button::LeftTrigger2) - trigger(Button::RightTrigger2);
+
+        let mut held = Vec::new();
+        if move_y > DEAD_ZONE {
+            held.push(Action::MoveForward);
+        } else if move_y < -DEAD_ZONE {
+            held.push(Action::MoveBackward);
+        }
+        if move_x > DEAD_ZONE {
+            held.push(Action::MoveRight);
+        } else if move_x < -DEAD_ZONE {
+            held.push(Action::MoveLeft);
+        }
+        for action in self.held.iter().filter(|action| !held.contains(action)) {
+            state
+                .camera_controller
+                .process_keyboard(*action, ElementState::Released);
+        }
+        for action in held.iter() {
+            state
+                .camera_controller
+                .process_keyboard(*action, ElementState::Pressed);
+        }
+        self.held = held;
+
+        if look_x.abs() > DEAD_ZONE || look_y.abs() > DEAD_ZONE {
+            // Pushing the stick up looks up, like moving the mouse up
+            state
+                .camera_controller
+                .process_mouse((look_x * LOOK_SPEED) as f64, (-look_y * LOOK_SPEED) as f64);
+        }
+        if zoom.abs() > 0.05 {
+            state
+                .camera_controller
+                .process_scroll(&MouseScrollDelta::LineDelta(0.0, zoom * ZOOM_SPEED));
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use winit::event::ModifiersState;
+use std::collections::HashMap;
+
+use cgmath::Vector2;
+use winit::event::{ModifiersState, VirtualKeyCode};
 
 bitflags::bitflags! {
     #[derive(Default)]
@@ -11,15 +14,21 @@ bitflags::bitflags! {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DragKind {
+    /// Pulling the cloth by the grid square at `(x, y)`
     Particle(usize, usize),
+    /// Looking around
+    Camera,
 }
 
 pub struct InputState {
     pub dragging: Option<DragKind>,
     pub modifier_state: ModifiersState,
     pub movement_state: MovementState,
+    /// Where each finger currently on the screen last was, by touch id, in
+    /// the same coordinates as `Mouse::pos`.
+    pub touches: HashMap<u64, Vector2<f32>>,
 }
 
 impl Default for InputState {
@@ -28,6 +37,115 @@ impl Default for InputState {
             dragging: Default::default(),
             modifier_state: Default::default(),
             movement_state: Default::default(),
+            touches: Default::default(),
+        }
+    }
+}
+
+/// Something a key can be bound to in `KeyBindings`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    ResetCamera,
+    FitCamera,
+    NarrowFov,
+    WidenFov,
+    TogglePause,
+    StepOnce,
+    SlowDown,
+    SpeedUp,
+    CycleBackground,
+    ToggleWireframe,
+    ToggleNormals,
+    /// Shows the ray the last click picked along, see `RayPipeline`.
+    TogglePickRay,
+    ToggleStats,
+    /// Shows or hides the `ui` feature's control panel.
+    ToggleUi,
+    Quit,
+}
+
+/// Which key does what. A key triggers at most one `Action`, but an action
+/// can have several keys, like W and Up for `Action::MoveForward`.
+pub struct KeyBindings {
+    bindings: HashMap<VirtualKeyCode, Action>,
+}
+
+impl KeyBindings {
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    /// Makes `key` trigger `action`, replacing whatever it did before.
+    pub fn bind(&mut self, key: VirtualKeyCode, action: Action) {
+        self.bindings.insert(key, action);
+    }
+
+    /// Removes every key bound to `action`.
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.retain(|_, bound| *bound != action);
+    }
+}
+
+impl Default for KeyBindings {
+    /// WASD or the arrow keys to move, E and Ctrl for up and down, Space to
+    /// pause.
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+
+        let bindings = [
+            (W, Action::MoveForward),
+            (Up, Action::MoveForward),
+            (S, Action::MoveBackward),
+            (Down, Action::MoveBackward),
+            (A, Action::MoveLeft),
+            (Left, Action::MoveLeft),
+            (D, Action::MoveRight),
+            (Right, Action::MoveRight),
+            (E, Action::MoveUp),
+            (LControl, Action::MoveDown),
+            (LShift, Action::MoveDown),
+            (Space, Action::TogglePause),
+            (Period, Action::StepOnce),
+            (Minus, Action::SlowDown),
+            (Equals, Action::SpeedUp),
+            (R, Action::ResetCamera),
+            (F, Action::FitCamera),
+            (LBracket, Action::NarrowFov),
+            (RBracket, Action::WidenFov),
+            (B, Action::CycleBackground),
+            (L, Action::ToggleWireframe),
+            (N, Action::ToggleNormals),
+            (P, Action::TogglePickRay),
+            (F3, Action::ToggleStats),
+            (F1, Action::ToggleUi),
+            (Escape, Action::Quit),
+        ];
+        Self {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particle_drag_keeps_its_square() {
+        let mut input = InputState::default();
+        assert_eq!(input.dragging, None);
+
+        input.dragging = Some(DragKind::Particle(3, 4));
+        match input.dragging {
+            Some(DragKind::Particle(x, y)) => assert_eq!((x, y), (3, 4)),
+            other => panic!("Expected a particle drag, got {:?}", other),
         }
+        assert_ne!(input.dragging, Some(DragKind::Camera));
     }
 }
@@ -1,11 +1,24 @@
+pub mod background;
 pub mod camera;
 pub mod cloth;
+#[cfg(feature = "compute")]
+pub mod compute;
+pub mod events;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
 pub mod input;
 pub mod main_state;
 pub mod memo;
 pub mod mouse;
+pub mod outline;
+pub mod overlay;
+pub mod picking;
+pub mod post;
 pub mod ray;
+pub mod skybox;
 pub mod texture;
+#[cfg(feature = "ui")]
+pub mod ui;
 
 #[cfg(feature = "debug")]
 pub mod debug;
@@ -15,17 +28,10 @@ use cfg_if::cfg_if;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use std::time::{SystemTime, UNIX_EPOCH};
-
 use bytemuck::{Pod, Zeroable};
 use cgmath::{vec2, ElementWise, Vector4};
-use main_state::State;
-use winit::{
-    dpi::LogicalSize,
-    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
+use main_state::{StartupError, State};
+use winit::{dpi::LogicalSize, event::Event, event_loop::EventLoop, window::WindowBuilder};
 
 cfg_if! {
     if #[cfg(target_arch = "wasm32")] {
@@ -35,18 +41,29 @@ cfg_if! {
     }
 }
 
-cfg_if! {
-    if #[cfg(target_os = "macos")] {
-// For MacOS bc retina screens double the amount of pixels
-        pub const SCREEN_SCALE: f32 = 2.0;
-    } else {
-        pub const SCREEN_SCALE: f32 = 1.0;
-    }
+/// Decodes an sRGB color, as written in CSS or a color picker, to the linear
+/// values the shaders and sRGB surface expect. Alpha is already linear.
+pub fn srgb_to_linear(rgba: Vector4<f32>) -> Vector4<f32> {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vector4::new(channel(rgba.x), channel(rgba.y), channel(rgba.z), rgba.w)
 }
 
-pub fn convert_to_srgba(rgba: Vector4<f32>) -> Vector4<f32> {
-    (rgba).add_element_wise(0.055).map(|val| val.powf(2.4))
-    // (rgba).map(|val| val.powf(2.4))
+/// The inverse of `srgb_to_linear`.
+pub fn linear_to_srgb(rgba: Vector4<f32>) -> Vector4<f32> {
+    let channel = |c: f32| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    Vector4::new(channel(rgba.x), channel(rgba.y), channel(rgba.z), rgba.w)
 }
 
 #[rustfmt::skip]
@@ -57,19 +74,25 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+/// Flips clip-space depth so the near plane lands on 1.0 and the far plane on
+/// 0.0, spreading float precision more evenly across the view distance.
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
 pub const SAFE_FRAC_PI_2: f32 = std::f32::consts::FRAC_PI_2 - 0.0001;
 
+/// Maps a pixel position, origin top left and y down, to normalized device
+/// coordinates in `[-1, 1]` with y up.
 pub fn screen_space_to_clip_space(
     width: f32,
     height: f32,
     pos: &cgmath::Vector2<f32>,
 ) -> cgmath::Vector2<f32> {
-    // (0, 0) -> (1920, 1080)
-    // (-960, -540) -> (960, 540)
-    // (-1, -1) -> (1, 1)
-    // let pos = cgmath::vec2(pos.x - (width), -(pos.y - (height)));
-    // let pos = cgmath::vec2(pos.x / (width), pos.y / (height));
-    // pos
     let screen_size = vec2(width, height);
 
     let mut ndc = pos.div_element_wise(screen_size) * 2.0 - vec2(1.0, 1.0);
@@ -77,14 +100,13 @@ pub fn screen_space_to_clip_space(
     ndc
 }
 
+/// The inverse of `screen_space_to_clip_space`.
 pub fn clip_space_to_screen_space(
     width: f32,
     height: f32,
     pos: &cgmath::Vector2<f32>,
 ) -> cgmath::Vector2<f32> {
-    let pos = cgmath::vec2(pos.x * width, pos.y * height);
-    let pos = cgmath::vec2(pos.x + width, height - pos.y);
-    pos
+    cgmath::vec2((pos.x + 1.0) * 0.5 * width, (1.0 - pos.y) * 0.5 * height)
 }
 
 pub fn screen_vec_to_clip_vec(
@@ -147,43 +169,50 @@ impl Vertex {
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub fn run() {
-    let mut frame: u128 = 0;
-    let mut start: u128 = 0;
+    // Set up first so startup errors can be reported. An embedder may have
+    // installed a logger already, in which case theirs is kept. Natively
+    // `RUST_LOG` overrides the level, per target too
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .try_init();
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = console_log::init_with_level(log::Level::Info);
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    }
 
     let event_loop = EventLoop::new();
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
-            let window = WindowBuilder::new()
+            let window_builder = WindowBuilder::new()
                 .with_maximized(true)
-                .with_resizable(true)
-                .build(&event_loop)
-                .unwrap();
+                .with_resizable(true);
         } else {
-            let window = WindowBuilder::new()
+            let window_builder = WindowBuilder::new()
                 .with_inner_size(LogicalSize {
                     width: 800,
                     height: 600,
                 })
-                .with_resizable(true)
-                .build(&event_loop)
-                .unwrap();
+                .with_resizable(true);
         }
     }
-
-    #[cfg(not(target_arch = "wasm32"))]
-    simple_logger::init_with_level(log::Level::Info).unwrap();
+    let window = match window_builder.build(&event_loop) {
+        Ok(window) => window,
+        Err(err) => {
+            log::error!("Couldn't start: {}", StartupError::Window(err));
+            return;
+        }
+    };
 
     #[cfg(target_arch = "wasm32")]
     {
-        console_log::init_with_level(log::Level::Info);
-        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
         // Winit prevents sizing with CSS, so we have to set
         // the size manually when on web.
         use winit::dpi::PhysicalSize;
         // window.set_inner_size(PhysicalSize::new(1600, 1200));
 
         use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
+        let appended = web_sys::window()
             .and_then(|win| win.document())
             .and_then(|doc| {
                 let dst = doc.get_element_by_id("wasm-example")?;
@@ -191,84 +220,62 @@ pub fn run() {
                 dst.append_child(&canvas).ok()?;
                 Some(())
             })
-            .expect("Couldn't append canvas to document body.");
+            .is_some();
+        if !appended {
+            log::error!("Couldn't start: failed to append the canvas to the document body");
+            return;
+        }
     }
 
-    let mut state = pollster::block_on(State::new(&window));
-    let mut last_render_time = instant::Instant::now();
+    // An image to print on the cloth: a path as the first argument natively,
+    // or an `?image=` URL on the web, which has to be fetched asynchronously
+    // and swapped in once it arrives
+    #[cfg(not(target_arch = "wasm32"))]
+    let image = std::env::args()
+        .nth(1)
+        .map(|path| texture::ImageSource::Path(path.into()));
+    #[cfg(target_arch = "wasm32")]
+    let image = None;
+    #[cfg(target_arch = "wasm32")]
+    let fetched_image = fetch_image_param();
 
-    event_loop.run(move |event, _, control_flow| {
-        #[cfg(not(target_arch = "wasm32"))]
-        if start == 0 {
-            start = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
+    let mut state = match pollster::block_on(State::new(&window, image)) {
+        Ok(state) => state,
+        Err(err) => {
+            log::error!("Couldn't start: {}", err);
+            return;
         }
+    };
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = gamepad::Gamepad::new();
 
+    event_loop.run(move |event, _, control_flow| {
         match event {
             Event::DeviceEvent { event, .. } => {
-                state.device_input(&event);
+                state.handle_device_event(&event);
             }
             Event::WindowEvent {
                 ref event,
                 window_id,
             } if window_id == window.id() => {
-                if !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    state: ElementState::Pressed,
-                                    virtual_keycode: Some(VirtualKeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            // new_inner_size is &mut so w have to dereference it twice
-                            state.resize(**new_inner_size);
-                        }
-                        _ => {}
-                    }
-                }
+                state.handle_window_event(event, control_flow);
             }
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                let now = instant::Instant::now();
-                let dt = now - last_render_time;
-
-                last_render_time = now;
-                state.update(dt);
-
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
-                    }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // We're ignoring timeouts
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
-                }
-                frame += 1;
-
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    // std::thread::sleep(std::time::Duration::from_millis(100));
-                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                    if now.as_millis() - start > 1000 {
-                        let fps = frame as f64 / ((now.as_millis() - start) as f64 / 1000.0);
-                        // window.set_title(&format!("{:.1$} fps", fps, 3));
-                        window.set_title(&format!("{} fps — ", fps,));
+                #[cfg(target_arch = "wasm32")]
+                if let Some(bytes) = fetched_image.borrow_mut().take() {
+                    if let Err(err) = state.set_cloth_texture(&bytes) {
+                        log::warn!("Failed to load cloth image: {:?}", err);
                     }
                 }
+
+                state.render_frame(control_flow);
             }
             Event::MainEventsCleared => {
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepad) = gamepad.as_mut() {
+                    gamepad.poll(&mut state);
+                }
+
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
                 window.request_redraw();
@@ -278,33 +285,94 @@ pub fn run() {
     });
 }
 
+/// Mirrors the frame rate into the page's `#fps` element, if it has one.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn show_fps_in_page(fps: f32) {
+    if let Some(element) = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.get_element_by_id("fps"))
+    {
+        element.set_text_content(Some(&format!("{:.1} fps", fps)));
+    }
+}
+
+/// Starts fetching the image named by the page's `?image=` query parameter,
+/// if any. The bytes show up in the returned slot once downloaded.
+#[cfg(target_arch = "wasm32")]
+fn fetch_image_param() -> std::rc::Rc<std::cell::RefCell<Option<Vec<u8>>>> {
+    let slot = std::rc::Rc::<std::cell::RefCell<Option<Vec<u8>>>>::default();
+    let url = web_sys::window()
+        .and_then(|win| win.location().search().ok())
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+        .and_then(|params| params.get("image"));
+    if let Some(url) = url {
+        let slot = slot.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match texture::Texture::fetch_bytes(&url).await {
+                Ok(bytes) => *slot.borrow_mut() = Some(bytes),
+                Err(err) => log::warn!("Failed to fetch cloth image: {:?}", err),
+            }
+        });
+    }
+    slot
+}
+
+/// Why a string couldn't be parsed as a hex color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorParseError {
+    /// Not 3, 6 or 8 digits long, ignoring a leading `#`.
+    InvalidLength(String),
+    /// Contains something other than hex digits.
+    InvalidDigit(String),
+    /// A palette with no colors in it.
+    Empty,
+}
+
+impl std::fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLength(hex) => {
+                write!(f, "Hex color {:?} should have 3, 6 or 8 digits", hex)
+            }
+            Self::InvalidDigit(hex) => write!(f, "Hex color {:?} has a non-hex digit", hex),
+            Self::Empty => write!(f, "A palette needs at least one color"),
+        }
+    }
+}
+
+impl std::error::Error for ColorParseError {}
+
 pub struct ColorGenerator {
     pub colors: Vec<Vector4<f32>>,
     pub idx: usize,
 }
 
 impl ColorGenerator {
+    const DEFAULT_PALETTE: [&'static str; 15] = [
+        "5FB49C", "F2B134", "F93943", "6EF9F5", "B33C86", "E4FF1A", "FFB800", "FF5714", "FFEECF",
+        "4D9078", "D5F2E3", "FBF5F3", "C6CAED", "A288E3", "CCFFCB",
+    ];
+
     pub fn new() -> Self {
-        Self {
-            colors: vec![
-                Self::hex_to_rgba("5FB49C"),
-                Self::hex_to_rgba("F2B134"),
-                Self::hex_to_rgba("F93943"),
-                Self::hex_to_rgba("6EF9F5"),
-                Self::hex_to_rgba("B33C86"),
-                Self::hex_to_rgba("E4FF1A"),
-                Self::hex_to_rgba("FFB800"),
-                Self::hex_to_rgba("FF5714"),
-                Self::hex_to_rgba("FFEECF"),
-                Self::hex_to_rgba("4D9078"),
-                Self::hex_to_rgba("D5F2E3"),
-                Self::hex_to_rgba("FBF5F3"),
-                Self::hex_to_rgba("C6CAED"),
-                Self::hex_to_rgba("A288E3"),
-                Self::hex_to_rgba("CCFFCB"),
-            ],
-            idx: 0,
+        Self::from_hex(&Self::DEFAULT_PALETTE).expect("Default palette is valid hex")
+    }
+
+    /// Cycles through `colors`, which are used as is, so convert sRGB colors
+    /// with `srgb_to_linear` first. Fails if there are none.
+    pub fn from_colors(colors: Vec<Vector4<f32>>) -> Result<Self, ColorParseError> {
+        if colors.is_empty() {
+            return Err(ColorParseError::Empty);
         }
+        Ok(Self { colors, idx: 0 })
+    }
+
+    /// Cycles through a palette of hex colors, see `hex_to_rgba`.
+    pub fn from_hex(hex: &[&str]) -> Result<Self, ColorParseError> {
+        let colors = hex
+            .iter()
+            .map(|hex| Self::hex_to_rgba(hex))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_colors(colors)
     }
 
     pub fn next(&mut self) -> Vector4<f32> {
@@ -313,29 +381,34 @@ impl ColorGenerator {
         self.colors[idx].clone()
     }
 
-    fn hex_to_rgba(hex: &str) -> Vector4<f32> {
-        let mut hex = hex.to_string();
-        if hex.len() == 3 {
-            hex = format!(
-                "{}{}{}{}{}{}",
-                hex.chars().nth(0).unwrap(),
-                hex.chars().nth(0).unwrap(),
-                hex.chars().nth(1).unwrap(),
-                hex.chars().nth(1).unwrap(),
-                hex.chars().nth(2).unwrap(),
-                hex.chars().nth(2).unwrap()
-            );
+    /// Parses a 3 or 6 digit hex color, or 8 digits with alpha as in
+    /// `"FF000080"`, with or without a leading `#`, into the same converted
+    /// form `next` returns. Alpha isn't gamma converted, so it stays as given.
+    pub fn hex_to_rgba(hex: &str) -> Result<Vector4<f32>, ColorParseError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if ![3, 6, 8].contains(&digits.len()) {
+            return Err(ColorParseError::InvalidLength(hex.to_string()));
+        }
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ColorParseError::InvalidDigit(hex.to_string()));
         }
-        let r = u8::from_str_radix(&hex[0..2], 16).unwrap();
-        let g = u8::from_str_radix(&hex[2..4], 16).unwrap();
-        let b = u8::from_str_radix(&hex[4..6], 16).unwrap();
-        // let a = u8::from_str_radix(&hex[6..8], 16).unwrap_or(255);
-        convert_to_srgba(Vector4::new(
+        let digits: String = if digits.len() == 3 {
+            digits.chars().flat_map(|c| [c, c]).collect()
+        } else {
+            digits.to_string()
+        };
+        let channel = |i: usize| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| ColorParseError::InvalidDigit(hex.to_string()))
+        };
+        let (r, g, b) = (channel(0)?, channel(2)?, channel(4)?);
+        let a = if digits.len() == 8 { channel(6)? } else { 255 };
+        Ok(srgb_to_linear(Vector4::new(
             r as f32 / 255.0,
             g as f32 / 255.0,
             b as f32 / 255.0,
-            1.0,
-        ))
+            a as f32 / 255.0,
+        )))
     }
 }
 
@@ -346,3 +419,111 @@ impl Iterator for ColorGenerator {
         Some(self.next())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < EPSILON, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn srgb_half_is_about_a_fifth_linear() {
+        let linear = srgb_to_linear(Vector4::new(0.5, 0.5, 0.5, 1.0));
+        assert!((linear.x - 0.214).abs() < 1e-3, "{}", linear.x);
+    }
+
+    #[test]
+    fn srgb_curve_meets_at_the_breakpoint() {
+        let linear = srgb_to_linear(Vector4::new(0.04045, 0.0, 0.0, 1.0)).x;
+        assert_close(linear, 0.04045 / 12.92);
+        assert_close(linear, ((0.04045_f32 + 0.055) / 1.055).powf(2.4));
+    }
+
+    #[test]
+    fn linear_to_srgb_inverts_srgb_to_linear() {
+        for i in 0..=255 {
+            let c = i as f32 / 255.0;
+            let round_trip = linear_to_srgb(srgb_to_linear(Vector4::new(c, c, c, c)));
+            assert_close(round_trip.x, c);
+            assert_close(round_trip.w, c);
+        }
+    }
+
+    #[test]
+    fn hex_alpha_isnt_gamma_converted() {
+        let rgba = ColorGenerator::hex_to_rgba("FF000080").unwrap();
+        assert_close(rgba.x, 1.0);
+        assert_close(rgba.w, 128.0 / 255.0);
+        assert!((rgba.w - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn hex_lengths() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        for hex in ["F00", "#F00", "FF0000", "#FF0000", "FF0000FF"] {
+            assert_eq!(ColorGenerator::hex_to_rgba(hex), Ok(red), "{}", hex);
+        }
+        let gray = ColorGenerator::hex_to_rgba("888").unwrap();
+        assert_eq!(gray, ColorGenerator::hex_to_rgba("888888").unwrap());
+        assert_close(
+            gray.x,
+            srgb_to_linear(Vector4::new(136.0 / 255.0, 0.0, 0.0, 1.0)).x,
+        );
+    }
+
+    #[test]
+    fn hex_errors() {
+        assert_eq!(
+            ColorGenerator::hex_to_rgba("FF00"),
+            Err(ColorParseError::InvalidLength("FF00".to_string()))
+        );
+        assert_eq!(
+            ColorGenerator::hex_to_rgba("GG0000"),
+            Err(ColorParseError::InvalidDigit("GG0000".to_string()))
+        );
+    }
+
+    #[test]
+    fn screen_and_clip_space_round_trip() {
+        let (width, height) = (1280.0, 720.0);
+        let mut points = vec![
+            vec2(0.0, 0.0),
+            vec2(width, 0.0),
+            vec2(0.0, height),
+            vec2(width, height),
+            vec2(width / 2.0, height / 2.0),
+        ];
+        // A fixed xorshift so failures reproduce
+        let mut seed = 0x2545_f491_u32;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as f32 / u32::MAX as f32
+        };
+        for _ in 0..100 {
+            points.push(vec2(random() * width, random() * height));
+        }
+
+        for pos in points {
+            let ndc = screen_space_to_clip_space(width, height, &pos);
+            let back = clip_space_to_screen_space(width, height, &ndc);
+            assert!((back - pos).magnitude() < 1e-3, "{:?} -> {:?}", pos, back);
+        }
+    }
+
+    #[test]
+    fn cursor_corners_map_to_ndc_corners() {
+        let (width, height) = (1280.0, 720.0);
+        let top_left = screen_space_to_clip_space(width, height, &vec2(0.0, 0.0));
+        let bottom_right = screen_space_to_clip_space(width, height, &vec2(width, height));
+        assert_eq!(top_left, vec2(-1.0, 1.0));
+        assert_eq!(bottom_right, vec2(1.0, -1.0));
+    }
+}
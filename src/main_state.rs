@@ -1,57 +1,236 @@
-use cgmath::{vec3, vec4, InnerSpace, Rotation3, SquareMatrix, Transform, Vector4};
-use log::info;
+use cgmath::{vec4, EuclideanSpace, Point3, Rotation3, SquareMatrix, Transform, Vector3, Vector4};
+use log::{debug, info, trace};
 use wgpu::util::DeviceExt;
 use winit::{
     event::{
-        DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode,
-        WindowEvent,
+        DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseButton, Touch, TouchPhase,
+        VirtualKeyCode, WindowEvent,
     },
+    event_loop::ControlFlow,
     window::Window,
 };
 
+#[cfg(feature = "ui")]
+use crate::ui::Ui;
 use crate::{
+    background::Background,
     camera::{self, Camera, CameraController, CameraUniform, Projection},
-    cloth::Physics,
-    convert_to_srgba,
-    debug::Debug,
-    input::{DragKind, InputState, MovementState},
+    cloth::{ClothBuilder, FitMode, Physics, DEFAULT_DRAG_RADIUS},
+    debug::{Debug, NormalLines},
+    events::{ClothEvent, FrameStats},
+    input::{Action, DragKind, InputState, KeyBindings, MovementState},
     memo::Memoized,
     mouse::Mouse,
+    overlay::Overlay,
+    picking::Picking,
+    post::{AaMode, Bloom, Fxaa},
     ray::{Ray, RayPipeline},
     screen_space_to_clip_space,
-    texture::Texture,
-    ColorGenerator, SAMPLE_COUNT, SCREEN_SCALE,
+    skybox::Skybox,
+    srgb_to_linear,
+    texture::{DepthConfig, ImageSource, Texture, TextureOptions},
+    ColorGenerator, SAMPLE_COUNT,
 };
 
+/// Frames per second of simulated time in a recording, see
+/// `State::start_recording`.
+#[cfg(not(target_arch = "wasm32"))]
+pub const RECORDING_FPS: f32 = 30.0;
+
+/// The `log` target for picking and dragging diagnostics, e.g.
+/// `RUST_LOG=info,toilet_paper::picking=trace`. The variable is only read by
+/// native builds, the web build always logs at info.
+const PICKING_LOG: &str = "toilet_paper::picking";
+
+/// The anisotropic filtering used for the cloth where the adapter supports it.
+const MAX_ANISOTROPY: u16 = 16;
+
+/// The vertical field of view the camera starts with, in degrees.
+pub const DEFAULT_FOVY: f32 = 45.0;
+/// How many degrees `Action::NarrowFov` and `Action::WidenFov` change the field of view by.
+const FOVY_STEP: f32 = 5.0;
+/// What `Action::SlowDown` and `Action::SpeedUp` divide or multiply the time scale by.
+const TIME_SCALE_STEP: f32 = 2.0;
+
+/// An in-progress `State::start_recording`.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Recording {
+    dir: std::path::PathBuf,
+    frame: usize,
+    frame_count: usize,
+}
+
+/// What `State::render_frame` has counted since the stats overlay last
+/// changed.
+struct StatsCounter {
+    start: instant::Instant,
+    frames: u32,
+    substeps: usize,
+}
+
+impl StatsCounter {
+    fn new(start: instant::Instant) -> Self {
+        Self {
+            start,
+            frames: 0,
+            substeps: 0,
+        }
+    }
+}
+
+/// What `State::with_config` starts with. Everything can still be changed
+/// afterwards, this only saves building the default first.
+#[derive(Clone, Debug)]
+pub struct StateConfig {
+    /// The clear color, in sRGB.
+    pub background: Vector4<f32>,
+    /// MSAA samples per pixel, rounded down to what the adapter supports.
+    pub msaa: u32,
+    /// Where the camera starts. It looks at the middle of the cloth from
+    /// there.
+    pub camera_position: Point3<f32>,
+    /// The vertical field of view, in degrees.
+    pub fov_deg: f32,
+    /// The cloth, including the image printed on it. Its anisotropy is
+    /// lowered to 1 where the adapter can't filter anisotropically.
+    pub cloth: ClothBuilder,
+    /// Where the camera pose is saved on exit and loaded from on startup,
+    /// see `State::save_camera_pose`. `None` always starts from
+    /// `camera_position`.
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    pub camera_pose_file: Option<std::path::PathBuf>,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            background: vec4(20.0 / 256.0, 20.0 / 256.0, 28.0 / 256.0, 1.0),
+            msaa: SAMPLE_COUNT as u32,
+            camera_position: Point3::new(0.0, 0.0, 0.0),
+            fov_deg: DEFAULT_FOVY,
+            // Keep the tweet sharp where the paper folds away from the camera
+            cloth: ClothBuilder::new().texture_options(TextureOptions {
+                anisotropy: MAX_ANISOTROPY,
+                ..Default::default()
+            }),
+            #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+            camera_pose_file: Some("camera_pose.json".into()),
+        }
+    }
+}
+
+/// Why the window or `State` couldn't be created.
+#[derive(Debug)]
+pub enum StartupError {
+    Window(winit::error::OsError),
+    /// No adapter can draw to the window's surface.
+    NoAdapter,
+    Device(wgpu::RequestDeviceError),
+    /// The surface and adapter have no texture format in common.
+    NoSurfaceFormat,
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Window(err) => write!(f, "Failed to create the window: {}", err),
+            Self::NoAdapter => write!(
+                f,
+                "No GPU adapter can draw to this window, your GPU may not be supported"
+            ),
+            Self::Device(err) => write!(f, "Failed to open the GPU device: {}", err),
+            Self::NoSurfaceFormat => write!(f, "The window's surface has no supported formats"),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Window(err) => Some(err),
+            Self::Device(err) => Some(err),
+            Self::NoAdapter | Self::NoSurfaceFormat => None,
+        }
+    }
+}
+
 pub struct State {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
+    /// Physical pixels per logical pixel of the window's current monitor.
+    pub scale_factor: f32,
+    /// Set while the window has no area, see `resize`.
+    minimized: bool,
     pub depth_texture: Texture,
-    pub msaa_texture: Texture,
+    pub msaa_texture: Option<Texture>,
+    pub aa_mode: AaMode,
+    /// The MSAA sample counts the adapter can render with, ascending.
+    msaa_sample_counts: Vec<u32>,
+    pub depth: DepthConfig,
 
     pub physics: Physics,
+    pub background: Background,
+    pub skybox: Skybox,
+    pub bloom: Option<Bloom>,
+    pub picking: Picking,
+    pub fxaa: Option<Fxaa>,
+    pub overlay: Overlay,
+    #[cfg(feature = "ui")]
+    pub ui: Ui,
 
-    pub camera: Camera,
+    /// Marked updated on any change, like `projection`, so the camera buffer
+    /// is only rewritten when one of them changes.
+    pub camera: Memoized<Camera>,
     pub camera_controller: Memoized<CameraController>,
+    pub key_bindings: KeyBindings,
+    event_callback: Option<Box<dyn FnMut(ClothEvent)>>,
+    frame_stats_callback: Option<Box<dyn FnMut(FrameStats)>>,
+    /// Updates so far, see `FrameStats::frame_index`.
+    frame_index: u64,
+    /// When `render_frame` last ran, to time the next update.
+    last_frame: instant::Instant,
+    stats: StatsCounter,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
-    pub projection: Projection,
+    pub projection: Memoized<Projection>,
 
-    // pub ray_pipeline: RayPipeline,
     #[cfg(feature = "debug")]
     pub debug: Debug,
+    #[cfg(feature = "debug")]
+    pub normal_lines: NormalLines,
+    #[cfg(feature = "debug")]
+    pub ray_pipeline: RayPipeline,
 
     pub mouse: Mouse,
     pub input: InputState,
     pub bg: Vector4<f32>,
+    pub colors: ColorGenerator,
+    /// How many grid cells a drag grabs, see `Cloth::drag`.
+    pub drag_radius: f32,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub recording: Option<Recording>,
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    pub camera_pose_file: Option<std::path::PathBuf>,
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    /// `image` is printed on the cloth instead of the embedded tweet.
+    pub async fn new(window: &Window, image: Option<ImageSource>) -> Result<Self, StartupError> {
+        let mut config = StateConfig::default();
+        if let Some(image) = image {
+            config.cloth = config.cloth.image(image);
+        }
+        Self::with_config(window, config).await
+    }
+
+    pub async fn with_config(
+        window: &Window,
+        state_config: StateConfig,
+    ) -> Result<Self, StartupError> {
         let size = window.inner_size();
         let instance = wgpu::Instance::new(wgpu::Backends::all());
         let surface = unsafe { instance.create_surface(window) };
@@ -62,7 +241,19 @@ impl State {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .ok_or(StartupError::NoAdapter)?;
+
+        // Everything past these is optional, go without what's unsupported.
+        // Wireframe mode is only offered where lines are supported.
+        let wanted_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | wgpu::Features::POLYGON_MODE_LINE;
+        let features = wanted_features & adapter.features();
+        if features != wanted_features {
+            log::warn!(
+                "Adapter doesn't support {:?}, continuing without",
+                wanted_features - features
+            );
+        }
 
         let (device, queue) = adapter
             .request_device(
@@ -70,7 +261,7 @@ impl State {
                     label: None,
                     // features: wgpu::Features::DEPTH_CLIP_CONTROL,
                     // features: wgpu::Features::empty(),
-                    features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    features,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -85,11 +276,10 @@ impl State {
                 None, // Trace path
             )
             .await
-            .unwrap();
-
-        let color = ColorGenerator::new();
+            .map_err(StartupError::Device)?;
 
-        let format = surface.get_supported_formats(&adapter)[0];
+        let format = Self::preferred_surface_format(&surface.get_supported_formats(&adapter))
+            .ok_or(StartupError::NoSurfaceFormat)?;
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format,
@@ -99,12 +289,21 @@ impl State {
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
         surface.configure(&device, &config);
+        let msaa_sample_counts = Self::supported_sample_counts(&adapter, format);
 
         // let camera = Camera::new((0.0, 0.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let camera = Camera::new((0.0, 0.0, 00.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection =
-            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = CameraController::new(4.0 * 2.0, 4.0 * 3.0);
+        let mut camera = Camera::new(
+            state_config.camera_position,
+            cgmath::Deg(-90.0),
+            cgmath::Deg(-20.0),
+        );
+        let projection = camera::Projection::new(
+            config.width,
+            config.height,
+            cgmath::Deg(state_config.fov_deg),
+            0.1,
+            100.0,
+        );
         let mut camera_uniform = CameraUniform::new(config.width as f32, config.height as f32);
         camera_uniform.update_view_proj(&camera, &projection);
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -112,20 +311,7 @@ impl State {
             contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let camera_bind_group_layout = CameraUniform::create_bind_group_layout(&device);
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_bind_group_layout,
@@ -136,50 +322,123 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, SAMPLE_COUNT, "Depth");
-        let msaa_texture = Texture::create(&device, &config, None, "MSAA", SAMPLE_COUNT);
+        let aa_mode = Self::msaa_mode(&msaa_sample_counts, state_config.msaa);
+        let depth_texture =
+            Texture::create_depth_texture(&device, &config, aa_mode.sample_count() as u8, "Depth");
+        let msaa_texture = Self::create_msaa_texture(&device, &config, aa_mode);
 
-        let bg = convert_to_srgba(vec4(20.0 / 256.0, 20.0 / 256., 28.0 / 256., 1.0));
-        // let bg = convert_to_srgba(vec4(255.0 / 256.0, 255.0 / 256., 255.0 / 256., 1.0));
+        let bg = srgb_to_linear(state_config.background);
+        // let bg = srgb_to_linear(vec4(255.0 / 256.0, 255.0 / 256., 255.0 / 256., 1.0));
 
-        let ray_pipeline = RayPipeline::new(
+        let mut cloth = state_config.cloth;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING)
+        {
+            cloth = cloth.limit_anisotropy(1);
+        }
+        let physics = Physics::new(
             &device,
             &queue,
-            config.format,
-            &camera,
-            &projection,
+            format,
+            aa_mode.sample_count(),
             &camera_bind_group_layout,
-            &config,
+            cloth,
         );
+        // Orbit around the middle of the cloth
+        let (min, max) = physics.cloth().bounding_box();
+        camera.set_target(Point3::from_vec((min + max) / 2.0));
+        let camera_controller = CameraController::new(4.0 * 2.0, 1.0, &camera);
 
-        Self {
-            physics: Physics::new(&device, &queue, format, &camera_bind_group_layout),
+        let state = Self {
+            physics,
+            background: Background::new(&device, &config, aa_mode.sample_count()),
+            skybox: Skybox::new(&device, &config, aa_mode.sample_count()),
+            bloom: None,
+            fxaa: None,
+            overlay: Overlay::new(&device, &config),
+            #[cfg(feature = "ui")]
+            ui: Ui::new(&device, format),
+            picking: Picking::new(&device, &config, &camera_bind_group_layout),
             surface,
             queue,
             config,
             size,
+            scale_factor: window.scale_factor() as f32,
+            minimized: false,
             depth_texture,
             msaa_texture,
+            aa_mode,
+            msaa_sample_counts,
+            depth: DepthConfig::default(),
 
-            camera,
+            camera: camera.into(),
             camera_controller: camera_controller.into(),
+            key_bindings: KeyBindings::default(),
+            event_callback: None,
+            frame_stats_callback: None,
+            frame_index: 0,
+            last_frame: instant::Instant::now(),
+            stats: StatsCounter::new(instant::Instant::now()),
             camera_uniform,
             camera_buffer,
             camera_bind_group,
-            projection,
+            projection: projection.into(),
 
-            // ray_pipeline,
             #[cfg(feature = "debug")]
             debug: Debug::new(&device),
+            #[cfg(feature = "debug")]
+            normal_lines: NormalLines::new(
+                &device,
+                format,
+                aa_mode.sample_count(),
+                DepthConfig::default().compare,
+                &camera_bind_group_layout,
+            ),
+            #[cfg(feature = "debug")]
+            ray_pipeline: RayPipeline::new(
+                &device,
+                format,
+                aa_mode.sample_count(),
+                &camera_bind_group_layout,
+            ),
 
             bg,
+            colors: ColorGenerator::new(),
+            drag_radius: DEFAULT_DRAG_RADIUS,
+            #[cfg(not(target_arch = "wasm32"))]
+            recording: None,
+            #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+            camera_pose_file: state_config.camera_pose_file,
             device,
             mouse: Mouse::default(),
             input: InputState::default(),
-        }
+        };
+        // Done after the camera controller is made, so resetting the camera
+        // still goes back to `camera_position`
+        #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+        let state = {
+            let mut state = state;
+            if let Some(path) = state.camera_pose_file.clone() {
+                // Nothing saved yet on the first run
+                if path.exists() {
+                    if let Err(err) = state.load_camera_pose(&path) {
+                        log::warn!("Couldn't load the camera pose from {:?}: {:#}", path, err);
+                    }
+                }
+            }
+            state
+        };
+        Ok(state)
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // The panel gets first pick, clicks on it shouldn't grab the cloth
+        #[cfg(feature = "ui")]
+        if self.ui.on_event(event, self.scale_factor) {
+            return true;
+        }
         match event {
             WindowEvent::MouseInput { state, button, .. } => {
                 self.input.movement_state.set(
@@ -188,6 +447,10 @@ impl State {
                 );
                 true
             }
+            WindowEvent::Touch(touch) => {
+                self.touch(touch);
+                true
+            }
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
@@ -198,16 +461,7 @@ impl State {
                 true
             }
             WindowEvent::CursorMoved { position, .. } => {
-                let vec: cgmath::Vector2<f32> = (
-                    position.x as f32 / SCREEN_SCALE,
-                    position.y as f32 / SCREEN_SCALE,
-                )
-                    .into();
-                // vec.x -= self.config.width as f32 / 2.0;
-                // vec.y -= self.config.height as f32 / 2.0;
-                // vec.x *= 2.0;
-                // vec.y *= -1.0;
-                self.mouse.pos = Some(vec);
+                self.mouse.pos = Some(cgmath::vec2(position.x as f32, position.y as f32));
                 true
             }
             WindowEvent::KeyboardInput {
@@ -247,40 +501,83 @@ impl State {
                     },
                 ..
             } => {
+                let action = match self.key_bindings.action(*key) {
+                    Some(action) => action,
+                    None => return false,
+                };
                 if self
                     .camera_controller
-                    .process_keyboard(*key, *element_state)
+                    .process_keyboard(action, *element_state)
                 {
                     return true;
                 }
+                if *element_state != ElementState::Pressed {
+                    return false;
+                }
 
-                match key {
-                    VirtualKeyCode::W => {
-                        self.input.movement_state.set(
-                            MovementState::W,
-                            matches!(element_state, ElementState::Pressed),
-                        );
+                match action {
+                    Action::TogglePause => {
+                        let paused = !self.physics.paused();
+                        self.physics.set_paused(paused);
                         true
                     }
-                    VirtualKeyCode::A => {
-                        self.input.movement_state.set(
-                            MovementState::A,
-                            matches!(element_state, ElementState::Pressed),
-                        );
+                    Action::StepOnce => {
+                        self.physics.step_once(&self.device, &self.queue);
                         true
                     }
-                    VirtualKeyCode::S => {
-                        self.input.movement_state.set(
-                            MovementState::S,
-                            matches!(element_state, ElementState::Pressed),
-                        );
+                    Action::SlowDown => {
+                        self.adjust_time_scale(1.0 / TIME_SCALE_STEP);
                         true
                     }
-                    VirtualKeyCode::D => {
-                        self.input.movement_state.set(
-                            MovementState::D,
-                            matches!(element_state, ElementState::Pressed),
-                        );
+                    Action::SpeedUp => {
+                        self.adjust_time_scale(TIME_SCALE_STEP);
+                        true
+                    }
+                    Action::CycleBackground => {
+                        let color = self.colors.next();
+                        self.set_background(color, false);
+                        true
+                    }
+                    #[cfg(feature = "debug")]
+                    Action::ToggleNormals => {
+                        self.normal_lines.enabled = !self.normal_lines.enabled;
+                        true
+                    }
+                    #[cfg(feature = "debug")]
+                    Action::TogglePickRay => {
+                        self.ray_pipeline.enabled = !self.ray_pipeline.enabled;
+                        true
+                    }
+                    Action::NarrowFov => {
+                        self.adjust_fovy(cgmath::Deg(-FOVY_STEP));
+                        true
+                    }
+                    Action::WidenFov => {
+                        self.adjust_fovy(cgmath::Deg(FOVY_STEP));
+                        true
+                    }
+                    Action::ResetCamera => {
+                        // Memoized has its own reset, so go through to the controller
+                        (*self.camera_controller).reset(&mut self.camera);
+                        true
+                    }
+                    Action::FitCamera => {
+                        self.fit_camera_to_cloth();
+                        true
+                    }
+                    Action::ToggleStats => {
+                        self.overlay.visible = !self.overlay.visible;
+                        true
+                    }
+                    #[cfg(feature = "ui")]
+                    Action::ToggleUi => {
+                        self.ui.visible = !self.ui.visible;
+                        true
+                    }
+                    Action::ToggleWireframe => {
+                        let mut cloth = self.physics.cloth();
+                        let wireframe = !cloth.wireframe();
+                        cloth.set_wireframe(&self.device, wireframe);
                         true
                     }
                     _ => false,
@@ -290,13 +587,122 @@ impl State {
         }
     }
 
-    pub fn device_input(&mut self, event: &DeviceEvent) -> bool {
+    /// Handles an event for this state's window the way `run` does, for
+    /// embedders driving their own event loop: the cloth and camera get it
+    /// first, then resizes and scale factor changes. Closing the window or
+    /// `Action::Quit` sets `control_flow` to exit, after saving the camera
+    /// pose if `StateConfig::camera_pose_file` is set.
+    pub fn handle_window_event(&mut self, event: &WindowEvent, control_flow: &mut ControlFlow) {
+        if self.input(event) {
+            return;
+        }
+        match event {
+            WindowEvent::CloseRequested => self.exit(control_flow),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } if self.key_bindings.action(*key) == Some(Action::Quit) => self.exit(control_flow),
+            WindowEvent::Resized(physical_size) => {
+                self.resize(*physical_size);
+            }
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                self.scale_factor = *scale_factor as f32;
+                // new_inner_size is &mut so w have to dereference it twice
+                self.resize(**new_inner_size);
+            }
+            _ => {}
+        }
+    }
+
+    fn exit(&mut self, control_flow: &mut ControlFlow) {
+        #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+        if let Some(path) = self.camera_pose_file.as_ref() {
+            if let Err(err) = self.save_camera_pose(path) {
+                log::warn!("Couldn't save the camera pose to {:?}: {:#}", path, err);
+            }
+        }
+        *control_flow = ControlFlow::Exit;
+    }
+
+    /// Writes the camera's position, angles and field of view to `path` as
+    /// JSON.
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    pub fn save_camera_pose(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let pose = self.camera.pose(&self.projection);
+        std::fs::write(path, serde_json::to_string_pretty(&pose)?)?;
+        Ok(())
+    }
+
+    /// Moves the camera to a pose `save_camera_pose` wrote, see
+    /// `Camera::set_pose` for what happens if the window's shape changed.
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    pub fn load_camera_pose(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let pose: camera::CameraPose = serde_json::from_slice(&std::fs::read(path)?)?;
+        let mut values = pose
+            .position
+            .iter()
+            .chain([&pose.yaw, &pose.pitch, &pose.distance]);
+        anyhow::ensure!(
+            values.all(|v| v.is_finite()),
+            "pose isn't finite: {:?}",
+            pose
+        );
+        self.camera.set_pose(&pose, &mut self.projection);
+        Ok(())
+    }
+
+    /// Updates by the time since the last call and draws the frame, for a
+    /// `RedrawRequested` event. A lost or outdated surface is reconfigured;
+    /// running out of memory sets `control_flow` to exit.
+    pub fn render_frame(&mut self, control_flow: &mut ControlFlow) {
+        let now = instant::Instant::now();
+        let dt = now - self.last_frame;
+        self.last_frame = now;
+        self.update(dt);
+
+        match self.render() {
+            Ok(_) => {}
+            // Reconfigure the surface if it's lost or outdated
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => self.resize(self.size),
+            // The system is out of memory, we should probably quit
+            Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+            // We're ignoring timeouts
+            Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
+        }
+
+        self.stats.frames += 1;
+        self.stats.substeps += self.physics.substeps();
+        let elapsed = (now - self.stats.start).as_secs_f32();
+        if elapsed >= 1.0 {
+            let fps = self.stats.frames as f32 / elapsed;
+            let substeps = self.stats.substeps as f32 / self.stats.frames as f32;
+            self.set_frame_stats(fps, substeps);
+            #[cfg(target_arch = "wasm32")]
+            crate::show_fps_in_page(fps);
+            self.stats = StatsCounter::new(now);
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) -> bool {
         match event {
             // DeviceEvent::Key(KeyboardInput {
             //     virtual_keycode: Some(VirtualKeycode::),
             //     ..
             // }) => {}
             DeviceEvent::MouseMotion { delta } => {
+                // A drag that started off the panel keeps going over it
+                #[cfg(feature = "ui")]
+                if self.input.dragging.is_none() && self.ui.wants_pointer() {
+                    return false;
+                }
                 if self
                     .input
                     .movement_state
@@ -306,9 +712,15 @@ impl State {
                         let dx = delta.0 as f32 * 2.0;
                         let dy = -delta.1 as f32 * 2.0;
 
-                        self.physics.cloth.mouse_force(*x, *y, dx, dy);
-                        self.physics.cloth.mouse_force(*x - 1, *y - 1, dx, dy);
-                        self.physics.cloth.mouse_force(*x + 1, *y + 1, dx, dy);
+                        trace!(
+                            target: PICKING_LOG,
+                            "Dragging ({}, {}) by ({}, {})",
+                            x,
+                            y,
+                            dx,
+                            dy
+                        );
+                        self.physics.cloth().drag(*x, *y, dx, dy, self.drag_radius);
                     } else {
                         self.camera_controller.process_mouse(delta.0, delta.1);
                     }
@@ -316,42 +728,30 @@ impl State {
             }
             DeviceEvent::Button { state, .. } => match state {
                 ElementState::Pressed => {
+                    #[cfg(feature = "ui")]
+                    if self.ui.wants_pointer() {
+                        return false;
+                    }
                     self.input
                         .movement_state
                         .set(MovementState::MOUSE_PRESSED, true);
 
                     if !self.input.modifier_state.contains(ModifiersState::SHIFT) {
+                        self.set_dragging(Some(DragKind::Camera));
                         return false;
                     }
 
-                    // let pos = self.mouse.pos.expect("Should be good");
-                    let pos = screen_space_to_clip_space(
-                        self.config.width as f32 / 2.0,
-                        self.config.height as f32 / 2.0,
-                        &self.mouse.pos.expect("Should be good"),
-                    );
-                    let inv_view = self.camera.calc_matrix().invert().unwrap();
-                    let inv_proj = self.projection.calc_matrix().invert().unwrap();
-
-                    let pos_near = inv_view * inv_proj * vec4(pos.x, pos.y, 0.1, 1.0);
-                    let pos_near = pos_near.truncate() / pos_near.w;
-
-                    let pos_far = inv_view * inv_proj * vec4(pos.x, pos.y, 100.0, 1.0);
-                    let pos_far = pos_far.truncate() / pos_far.w;
-
-                    let dir = (pos_near - pos_far).normalize();
-                    let ray = Ray::new(
-                        vec3(
-                            self.camera.position.x,
-                            self.camera.position.y,
-                            self.camera.position.z,
-                        ),
-                        dir,
+                    #[cfg(feature = "debug")]
+                    self.show_pick_ray();
+                    let hit = self.pick();
+                    debug!(
+                        target: PICKING_LOG,
+                        "Mouse pick at {:?}: {:?}",
+                        self.mouse.pos,
+                        hit
                     );
-                    let hit = self.physics.cloth.intersects(&ray);
                     if let Some((x, y)) = hit {
-                        println!("HIT: {:?}", hit);
-                        self.input.dragging = Some(DragKind::Particle(x, y));
+                        self.set_dragging(Some(DragKind::Particle(x, y)));
                         // self.physics.cloth.set_moveable(x, y, false);
                     }
                 }
@@ -360,10 +760,10 @@ impl State {
                         .movement_state
                         .set(MovementState::MOUSE_PRESSED, false);
 
-                    if let Some(DragKind::Particle(x, y)) = self.input.dragging {
-                        // self.physics.cloth.set_moveable(x, y, true);
-                        self.input.dragging = None;
-                    }
+                    // if let Some(DragKind::Particle(x, y)) = self.input.dragging {
+                    //     self.physics.cloth.set_moveable(x, y, true);
+                    // }
+                    self.set_dragging(None);
                 }
             },
             _ => (),
@@ -371,62 +771,613 @@ impl State {
         false
     }
 
+    /// One finger drags the cloth like shift + mouse does, two or more orbit
+    /// the camera.
+    fn touch(&mut self, touch: &Touch) {
+        let pos = cgmath::vec2(touch.location.x as f32, touch.location.y as f32);
+        match touch.phase {
+            TouchPhase::Started => {
+                self.input.touches.insert(touch.id, pos);
+                if self.input.touches.len() == 1 {
+                    self.mouse.pos = Some(pos);
+                    #[cfg(feature = "debug")]
+                    self.show_pick_ray();
+                    let hit = self.pick();
+                    debug!(target: PICKING_LOG, "Touch pick at {:?}: {:?}", pos, hit);
+                    if let Some((x, y)) = hit {
+                        self.set_dragging(Some(DragKind::Particle(x, y)));
+                    }
+                } else {
+                    self.set_dragging(Some(DragKind::Camera));
+                }
+            }
+            TouchPhase::Moved => {
+                let last = match self.input.touches.insert(touch.id, pos) {
+                    Some(last) => last,
+                    None => return,
+                };
+                let delta = (pos - last) / self.scale_factor;
+                match self.input.dragging {
+                    Some(DragKind::Particle(x, y)) => {
+                        trace!(
+                            target: PICKING_LOG,
+                            "Dragging ({}, {}) by {:?}",
+                            x,
+                            y,
+                            delta
+                        );
+                        self.physics.cloth().drag(
+                            x,
+                            y,
+                            delta.x * 2.0,
+                            -delta.y * 2.0,
+                            self.drag_radius,
+                        );
+                    }
+                    Some(DragKind::Camera) => {
+                        // Every finger reports its own move, so average them
+                        let fingers = self.input.touches.len() as f32;
+                        self.camera_controller
+                            .process_mouse((delta.x / fingers) as f64, (delta.y / fingers) as f64);
+                    }
+                    None => (),
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.input.touches.remove(&touch.id);
+                if self.input.touches.is_empty() {
+                    self.set_dragging(None);
+                }
+            }
+        }
+    }
+
+    /// Returns the grid square under the cursor.
+    pub fn pick(&self) -> Option<(usize, usize)> {
+        // Reading back from the GPU can't block on the web
+        if cfg!(target_arch = "wasm32") {
+            return self.pick_with_ray();
+        }
+        let pos = self.mouse.pos?;
+        self.picking.pick(
+            &self.device,
+            &self.queue,
+            &self.camera_bind_group,
+            &self.physics.cloth(),
+            pos.x as u32,
+            pos.y as u32,
+        )
+    }
+
+    /// Picks by casting a ray from the camera through the cursor on the CPU.
+    pub fn pick_with_ray(&self) -> Option<(usize, usize)> {
+        let (pos_near, pos_far) = self.cursor_ray()?;
+        let ray = Ray::new(pos_near, pos_far - pos_near);
+        let mut cloth = self.physics.cloth();
+        #[cfg(feature = "compute")]
+        cloth.sync_from_gpu(&self.device, &self.queue);
+        cloth.intersects(&ray)
+    }
+
+    /// Where the line through the cursor crosses the near and far planes.
+    fn cursor_ray(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let pos = screen_space_to_clip_space(
+            self.config.width as f32,
+            self.config.height as f32,
+            &self.mouse.pos?,
+        );
+        let inv_view = self.camera.calc_matrix().invert().unwrap();
+        let inv_proj = self.projection.calc_matrix().invert().unwrap();
+
+        // Unproject the cursor onto the near and far planes
+        let (near_z, far_z) = if self.projection.reverse_z() {
+            (1.0, 0.0)
+        } else {
+            (0.0, 1.0)
+        };
+        let pos_near = inv_view * inv_proj * vec4(pos.x, pos.y, near_z, 1.0);
+        let pos_near = pos_near.truncate() / pos_near.w;
+
+        let pos_far = inv_view * inv_proj * vec4(pos.x, pos.y, far_z, 1.0);
+        let pos_far = pos_far.truncate() / pos_far.w;
+        Some((pos_near, pos_far))
+    }
+
+    /// Hands the ray through the cursor to `ray_pipeline`, so the next pick
+    /// can be seen once the camera moves off it.
+    #[cfg(feature = "debug")]
+    fn show_pick_ray(&mut self) {
+        if !self.ray_pipeline.enabled {
+            return;
+        }
+        if let Some((pos_near, pos_far)) = self.cursor_ray() {
+            self.ray_pipeline.update(&self.queue, pos_near, pos_far);
+        }
+    }
+
+    /// Widens (positive `delta`) or narrows the field of view, leaving
+    /// orthographic mode if it's on.
+    pub fn adjust_fovy(&mut self, delta: cgmath::Deg<f32>) {
+        let fovy = self
+            .projection
+            .fovy()
+            .map(cgmath::Deg::from)
+            .unwrap_or(cgmath::Deg(DEFAULT_FOVY));
+        self.projection.set_fovy(fovy + delta);
+    }
+
+    /// Multiplies the simulation speed by `factor`, see `Physics::set_time_scale`.
+    pub fn adjust_time_scale(&mut self, factor: f32) {
+        let time_scale = self.physics.time_scale() * factor;
+        self.physics.set_time_scale(time_scale);
+        info!("Time scale {}", self.physics.time_scale());
+    }
+
+    /// Re-centers the camera on the cloth wherever it has fallen to.
+    pub fn fit_camera_to_cloth(&mut self) {
+        let (min, max) = self.physics.cloth().bounding_box();
+        let distance = self.camera.fit_to_bounds(min, max, &mut self.projection);
+        info!("Fit camera to cloth at distance {}", distance);
+    }
+
     pub fn set_dragging(&mut self, dragging: Option<DragKind>) {
-        self.input.dragging = dragging;
+        let previous = std::mem::replace(&mut self.input.dragging, dragging);
+        if previous == dragging {
+            return;
+        }
+        if let Some(DragKind::Particle(x, y)) = previous {
+            self.emit(ClothEvent::Released { x, y });
+        }
+        if let Some(DragKind::Particle(x, y)) = dragging {
+            self.emit(ClothEvent::Grabbed { x, y });
+        }
+    }
+
+    /// Calls `callback` whenever a particle is grabbed or released, or the
+    /// cloth tears, e.g. to play a sound.
+    pub fn set_event_callback(&mut self, callback: impl FnMut(ClothEvent) + 'static) {
+        self.event_callback = Some(Box::new(callback));
     }
 
+    /// Calls `callback` after every `update` with how long the frame took
+    /// and how much simulating it needed, e.g. to graph performance.
+    pub fn set_frame_stats_callback(&mut self, callback: impl FnMut(FrameStats) + 'static) {
+        self.frame_stats_callback = Some(Box::new(callback));
+    }
+
+    fn emit(&mut self, event: ClothEvent) {
+        if let Some(callback) = self.event_callback.as_mut() {
+            callback(event);
+        }
+    }
+
+    /// Sets the clear color behind everything else. Pass `srgb` for colors
+    /// as written in CSS or a color picker so they're converted to match;
+    /// `ColorGenerator` colors are already converted and can go straight in.
+    pub fn set_background(&mut self, color: Vector4<f32>, srgb: bool) {
+        self.bg = if srgb { srgb_to_linear(color) } else { color };
+    }
+
+    /// Like `set_background`, from a hex color such as `"#14141c"` or
+    /// `"fff"`, e.g. to match the page the canvas is embedded in.
+    pub fn set_background_hex(&mut self, hex: &str) -> anyhow::Result<()> {
+        let color = ColorGenerator::hex_to_rgba(hex)?;
+        self.set_background(color, false);
+        Ok(())
+    }
+
+    /// Draws `texture` full-screen behind the cloth.
+    pub fn set_background_image(&mut self, texture: Texture) {
+        self.background
+            .set_image(&self.device, &self.queue, texture);
+    }
+
+    /// Prints a different image on the cloth, e.g. a screenshot of another
+    /// tweet. Accepts anything `image` can decode as PNG or JPEG.
+    pub fn set_cloth_texture(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.physics
+            .cloth()
+            .set_texture(&self.device, &self.queue, bytes)
+    }
+
+    pub fn set_background_fit(&mut self, fit: FitMode) {
+        self.background.set_fit(&self.queue, fit);
+    }
+
+    /// Loads a cubemap from six images in `+X, -X, +Y, -Y, +Z, -Z` order and
+    /// draws it behind the scene.
+    pub fn set_skybox(&mut self, faces: [&[u8]; 6]) -> anyhow::Result<()> {
+        let cubemap = Texture::create_cubemap(&self.device, &self.queue, faces, "skybox")?;
+        self.physics
+            .cloth()
+            .set_environment(&self.device, Some(&cubemap));
+        self.skybox.set_cubemap(&self.device, cubemap);
+        self.skybox
+            .update(&self.queue, &self.camera, &self.projection);
+        Ok(())
+    }
+
+    /// Turns the glow around bright parts of the frame on or off.
+    pub fn set_bloom(&mut self, enabled: bool, threshold: f32, intensity: f32) {
+        if !enabled {
+            self.bloom = None;
+            return;
+        }
+        let bloom = self
+            .bloom
+            .get_or_insert_with(|| Bloom::new(&self.device, &self.config));
+        bloom.set_params(&self.queue, threshold, intensity);
+    }
+
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        aa_mode: AaMode,
+    ) -> Option<Texture> {
+        let sample_count = aa_mode.sample_count();
+        if sample_count > 1 {
+            Some(Texture::create(
+                device,
+                config,
+                None,
+                "MSAA",
+                sample_count as u8,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Picks an sRGB surface format so the GPU encodes the shaders' linear
+    /// output on write. Colors are kept linear everywhere before that, which
+    /// is why hex and palette colors go through `srgb_to_linear` first: on
+    /// a non-sRGB surface they come out too dark. `None` if the surface
+    /// supports no formats at all.
+    fn preferred_surface_format(formats: &[wgpu::TextureFormat]) -> Option<wgpu::TextureFormat> {
+        const PREFERRED: [wgpu::TextureFormat; 2] = [
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        ];
+        let srgb = PREFERRED
+            .iter()
+            .copied()
+            .find(|format| formats.contains(format))
+            .or_else(|| formats.iter().copied().find(|f| f.describe().srgb));
+        match srgb {
+            Some(format) => {
+                info!("Using surface format {:?}", format);
+                Some(format)
+            }
+            None => {
+                let format = formats.first().copied()?;
+                log::warn!(
+                    "No sRGB surface format available, using {:?}; colors will look darker",
+                    format
+                );
+                Some(format)
+            }
+        }
+    }
+
+    /// The MSAA sample counts the main pass can use with `format`. wgpu 0.14
+    /// only reports whether a format can be multisampled at all, and 4x is
+    /// the one count it guarantees then.
+    fn supported_sample_counts(adapter: &wgpu::Adapter, format: wgpu::TextureFormat) -> Vec<u32> {
+        let color = adapter.get_texture_format_features(format).flags;
+        let depth = adapter
+            .get_texture_format_features(Texture::DEPTH_FORMAT)
+            .flags;
+        let msaa = color.contains(
+            wgpu::TextureFormatFeatureFlags::MULTISAMPLE
+                | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE,
+        ) && depth.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE);
+        if msaa {
+            vec![1, 4]
+        } else {
+            vec![1]
+        }
+    }
+
+    /// The MSAA sample count the main pass renders with, 1 without MSAA.
+    pub fn msaa(&self) -> u32 {
+        self.aa_mode.sample_count()
+    }
+
+    /// The sample counts `set_msaa` can pick from, ascending.
+    pub fn msaa_sample_counts(&self) -> &[u32] {
+        &self.msaa_sample_counts
+    }
+
+    /// Multisamples the main pass with `sample_count` samples, or the most
+    /// the adapter supports below that, rebuilding the attachments and
+    /// pipelines to match. 1 turns MSAA off.
+    pub fn set_msaa(&mut self, sample_count: u32) {
+        self.set_antialiasing(Self::msaa_mode(&self.msaa_sample_counts, sample_count));
+    }
+
+    /// `sample_count` samples if it's in `supported`, or the most below that.
+    fn msaa_mode(supported: &[u32], sample_count: u32) -> AaMode {
+        let supported = supported
+            .iter()
+            .copied()
+            .rev()
+            .find(|&count| count <= sample_count.max(1))
+            .unwrap_or(1);
+        if supported != sample_count {
+            log::warn!(
+                "{}x MSAA isn't supported, using {}x instead",
+                sample_count,
+                supported
+            );
+        }
+        if supported > 1 {
+            AaMode::Msaa(supported)
+        } else {
+            AaMode::None
+        }
+    }
+
+    /// Switches between MSAA, FXAA and no anti-aliasing, rebuilding every
+    /// pipeline and attachment that depends on the sample count.
+    pub fn set_antialiasing(&mut self, aa_mode: AaMode) {
+        if self.aa_mode == aa_mode {
+            return;
+        }
+        self.aa_mode = aa_mode;
+
+        let sample_count = aa_mode.sample_count();
+        self.physics
+            .cloth()
+            .set_sample_count(&self.device, sample_count);
+        self.background.set_sample_count(&self.device, sample_count);
+        self.skybox.set_sample_count(&self.device, sample_count);
+        #[cfg(feature = "debug")]
+        self.normal_lines
+            .set_sample_count(&self.device, sample_count);
+        #[cfg(feature = "debug")]
+        self.ray_pipeline
+            .set_sample_count(&self.device, sample_count);
+
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            sample_count as u8,
+            "depth_texture",
+        );
+        self.msaa_texture = Self::create_msaa_texture(&self.device, &self.config, aa_mode);
+
+        self.fxaa = match aa_mode {
+            AaMode::Fxaa => Some(Fxaa::new(&self.device, &self.config)),
+            _ => None,
+        };
+    }
+
+    /// Changes how the depth buffer is cleared and tested.
+    pub fn set_depth_config(&mut self, depth: DepthConfig) {
+        self.depth = depth;
+        self.physics
+            .cloth()
+            .set_depth_compare(&self.device, depth.compare);
+        self.picking.set_depth(&self.device, depth);
+        #[cfg(feature = "debug")]
+        self.normal_lines
+            .set_depth_compare(&self.device, depth.compare);
+    }
+
+    /// Switches the camera to a reverse-Z projection along with the matching
+    /// depth clear and compare function.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.projection.set_reverse_z(reverse_z);
+        self.set_depth_config(if reverse_z {
+            DepthConfig::reverse_z()
+        } else {
+            DepthConfig::default()
+        });
+    }
+
+    /// Reconfigures the surface and recreates every attachment sized to it.
+    ///
+    /// All of them have to follow the surface: the MSAA target used to keep
+    /// its startup size, so with MSAA on, dragging the window larger or
+    /// smaller made every following frame fail validation or come out
+    /// corrupted. `encode_frame` asserts the sizes match in debug builds.
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        // UPDATED!
-        if new_size.width > 0 && new_size.height > 0 {
+        // Minimizing resizes to 0x0, which the surface can't be configured
+        // with. Rendering pauses until a real size comes back.
+        self.minimized = new_size.width == 0 || new_size.height == 0;
+        if !self.minimized {
             self.projection.resize(new_size.width, new_size.height);
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.background
+                .resize(&self.queue, new_size.width, new_size.height);
             self.depth_texture = Texture::create_depth_texture(
                 &self.device,
                 &self.config,
-                SAMPLE_COUNT,
+                self.aa_mode.sample_count() as u8,
                 "depth_texture",
             );
+            self.msaa_texture = Self::create_msaa_texture(&self.device, &self.config, self.aa_mode);
+            if let Some(bloom) = self.bloom.as_mut() {
+                bloom.resize(&self.device, &self.queue, &self.config);
+            }
+            self.picking.resize(&self.device, &self.config);
+            if let Some(fxaa) = self.fxaa.as_mut() {
+                fxaa.resize(&self.device, &self.queue, &self.config);
+            }
+            self.overlay
+                .resize(&self.queue, new_size.width, new_size.height);
         }
     }
 
+    /// Shows the frame rate and how many simulation steps a frame takes on
+    /// average in the stats overlay, toggled by `Action::ToggleStats`.
+    pub fn set_frame_stats(&mut self, fps: f32, substeps: f32) {
+        let text = format!("{:.1} FPS\n{:.1} STEPS", fps, substeps);
+        self.overlay
+            .set_text(&self.device, &text, self.scale_factor);
+    }
+
     pub fn update(&mut self, dt: std::time::Duration) {
-        // if let Some(mut camera_controller) = self.camera_controller.handle_updated() {
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform
-            .update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
-        // }
+        let camera_controller = &mut self.camera_controller;
+        self.camera
+            .modify(|camera| camera_controller.update_camera(camera, dt));
+        if self.camera.updated() || self.projection.updated() {
+            self.camera.reset();
+            self.projection.reset();
+            self.camera_uniform
+                .update_view_proj(&self.camera, &self.projection);
+            self.queue.write_buffer(
+                &self.camera_buffer,
+                0,
+                bytemuck::cast_slice(&[self.camera_uniform]),
+            );
+        }
+        self.skybox
+            .update(&self.queue, &self.camera, &self.projection);
+        self.physics
+            .cloth()
+            .set_view_axes(self.camera.right_vec(), self.camera.up_vec());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let substeps = if self.recording.is_some() {
+            self.record_frame();
+            self.physics.substeps()
+        } else {
+            self.update_physics(dt)
+        };
+        #[cfg(target_arch = "wasm32")]
+        let substeps = self.update_physics(dt);
+
+        // Drained either way so tears don't pile up without a callback
+        let torn: Vec<_> = {
+            let mut cloth = self.physics.cloth();
+            let torn = cloth.take_torn();
+            torn.into_iter()
+                .map(|(a, b)| ClothEvent::Torn {
+                    a: cloth.particle_coords(a as u32),
+                    b: cloth.particle_coords(b as u32),
+                })
+                .collect()
+        };
+        for event in torn {
+            self.emit(event);
+        }
+
+        if let Some(callback) = self.frame_stats_callback.as_mut() {
+            callback(FrameStats {
+                frame_index: self.frame_index,
+                dt,
+                substeps,
+                constraint_iterations: self.physics.cloth().constraint_iterations(),
+            });
+        }
+        self.frame_index += 1;
+
+        #[cfg(feature = "debug")]
+        self.normal_lines
+            .update(&self.device, &self.queue, self.physics.cloth().particles());
+
+        #[cfg(feature = "ui")]
+        if let Some(context) = self
+            .ui
+            .begin_frame([self.config.width, self.config.height], self.scale_factor)
+        {
+            crate::ui::controls(&context, self);
+            self.ui.end_frame();
+        }
+    }
 
-        // self.ray_pipeline
-        //     .update(&self.queue, &self.camera, &self.projection, &self.config);
-        self.physics.update(&self.queue, dt);
+    /// Returns how many fixed steps the cloth took, see `Physics::update`.
+    fn update_physics(&mut self, dt: std::time::Duration) -> usize {
+        #[cfg(feature = "compute")]
+        return self.physics.update_gpu(&self.device, &self.queue, dt);
+        #[cfg(not(feature = "compute"))]
+        return self.physics.update(&self.queue, dt);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if self.minimized {
+            return Ok(());
+        }
         let output = self.surface.get_current_texture()?;
-        let view = output
+        let output_view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        let (view, resolve_target) = if SAMPLE_COUNT > 1 {
-            (&self.msaa_texture.view, Some(&view))
-        } else {
-            (&view, None)
-        };
 
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        self.encode_frame(&mut encoder, &output_view);
+        // Left out of `encode_frame` so they never end up in recordings
+        #[cfg(feature = "ui")]
+        let ui_commands = self.ui.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &output_view,
+            [self.config.width, self.config.height],
+            self.scale_factor,
+        );
+        #[cfg(not(feature = "ui"))]
+        let ui_commands = vec![];
+        self.overlay.render(&mut encoder, &output_view);
+
+        self.queue.submit(
+            ui_commands
+                .into_iter()
+                .chain(std::iter::once(encoder.finish())),
+        );
+        output.present();
+
+        // Debugging
+        // wgpu::util::DownloadBuffer::read_buffer(
+        //     &self.device,
+        //     &self.queue,
+        //     &self.debug.buffer.slice(0..1024),
+        //     |result| {},
+        // );
+
+        Ok(())
+    }
+
+    /// Records the whole frame, scene and post-processing, ending up in
+    /// `output_view`.
+    fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, output_view: &wgpu::TextureView) {
+        // Every attachment has to match the surface, an MSAA target left at
+        // its old size after a resize used to corrupt every later frame
+        let surface_size = (self.config.width, self.config.height);
+        let attachments = std::iter::once(&self.depth_texture).chain(self.msaa_texture.as_ref());
+        for attachment in attachments {
+            debug_assert_eq!(
+                (attachment.size.width, attachment.size.height),
+                surface_size,
+                "Attachment wasn't resized with the surface"
+            );
+        }
+
+        // Post-processing needs the scene in a texture it can sample. FXAA
+        // runs last, so bloom writes into its input instead of the surface.
+        let post_view = match self.fxaa.as_ref() {
+            Some(fxaa) => fxaa.input_view(),
+            None => output_view,
+        };
+        let scene_view = match self.bloom.as_ref() {
+            Some(bloom) => bloom.scene_view(),
+            None => post_view,
+        };
+        let (view, resolve_target) = match self.msaa_texture.as_ref() {
+            Some(msaa_texture) => (&msaa_texture.view, Some(scene_view)),
+            None => (scene_view, None),
+        };
 
         {
+            // Held for the whole pass, which borrows the cloth's buffers
+            let cloth = self.physics.cloth();
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -446,32 +1397,205 @@ impl State {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(self.depth.clear),
+                        store: true,
+                    }),
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
                         store: true,
                     }),
-                    stencil_ops: None,
                 }),
             });
 
-            self.physics
-                .cloth
+            self.skybox.render(&mut render_pass);
+            self.background.render(&mut render_pass);
+
+            cloth.render(&self.camera_bind_group, &mut render_pass);
+            #[cfg(feature = "debug")]
+            self.normal_lines
                 .render(&self.camera_bind_group, &mut render_pass);
+            #[cfg(feature = "debug")]
+            self.ray_pipeline
+                .render(&mut render_pass, &self.camera_bind_group);
+        }
+
+        if let Some(bloom) = self.bloom.as_ref() {
+            bloom.render(encoder, post_view);
+        }
+        if let Some(fxaa) = self.fxaa.as_ref() {
+            fxaa.render(encoder, output_view);
+        }
+    }
+
+    /// Starts writing the next `frame_count` frames to `dir` as
+    /// `frame_00000.png`, `frame_00001.png`, ... While recording, every frame
+    /// advances the simulation by exactly `1 / RECORDING_FPS` seconds of fixed
+    /// steps instead of following the clock, so the same starting state
+    /// always gives the same frames however slow the machine is.
+    ///
+    /// Turn them into a GIF with e.g.
+    /// `ffmpeg -framerate 30 -i frame_%05d.png toilet-paper.gif`.
+    ///
+    /// Like `Physics::step_fixed`, this stops the simulation thread, so the
+    /// cloth steps on the render thread until the recording ends.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_recording(
+        &mut self,
+        dir: impl Into<std::path::PathBuf>,
+        frame_count: usize,
+    ) -> anyhow::Result<()> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        self.recording = Some(Recording {
+            dir,
+            frame: 0,
+            frame_count,
+        });
+        Ok(())
+    }
+
+    /// Stops a recording early, returning how many frames were written. The
+    /// simulation thread picks up in real time from where it left off.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_recording(&mut self) -> usize {
+        let frames = self.recording.take().map_or(0, |recording| recording.frame);
+        self.physics.start_simulation_thread();
+        frames
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn record_frame(&mut self) {
+        let steps = (self.physics.tick_rate() / RECORDING_FPS).round().max(1.0) as usize;
+        self.physics.step_fixed(steps);
+        self.physics.update_wgpu(&self.queue);
 
-            // self.ray_pipeline
-            //     .render(&mut render_pass, &self.camera_bind_group);
+        let recording = match self.recording.as_ref() {
+            Some(recording) => recording,
+            None => return,
+        };
+        let path = recording
+            .dir
+            .join(format!("frame_{:05}.png", recording.frame));
+        if let Err(err) = self.capture_to_file(&path) {
+            log::error!(
+                "Failed to write {}, stopping recording: {:?}",
+                path.display(),
+                err
+            );
+            self.stop_recording();
+            return;
         }
 
+        if let Some(recording) = self.recording.as_mut() {
+            recording.frame += 1;
+            if recording.frame >= recording.frame_count {
+                info!(
+                    "Recorded {} frames to {}",
+                    recording.frame,
+                    recording.dir.display()
+                );
+                self.stop_recording();
+            }
+        }
+    }
+
+    /// Renders the current frame offscreen and reads it back, e.g. to save
+    /// the scene as an image. Blocks until the GPU is done, which a browser
+    /// never lets happen, so this is native only.
+    ///
+    /// This renders into its own texture rather than the surface, so it fails
+    /// on an unsupported surface format or a failed readback, not with a
+    /// `wgpu::SurfaceError`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        let (width, height) = (self.config.width, self.config.height);
+        let swap_red_blue = match self.config.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => anyhow::bail!("Can't capture frames in {:?}", format),
+        };
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Buffer rows have to be a multiple of 256 bytes
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        self.encode_frame(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
 
-        // Debugging
-        // wgpu::util::DownloadBuffer::read_buffer(
-        //     &self.device,
-        //     &self.queue,
-        //     &self.debug.buffer.slice(0..1024),
-        //     |result| {},
-        // );
+        let slice = buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if swap_red_blue {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Captured frame has the wrong size"))
+    }
 
+    /// Saves the current frame to `path`, in whatever format its extension
+    /// names (e.g. `.png`).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_to_file(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.capture_frame()?.save(path)?;
         Ok(())
     }
 }
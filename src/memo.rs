@@ -59,6 +59,21 @@ impl<T> Memoized<T> {
         self.updated = false;
     }
 
+    /// Replaces the value and marks it updated.
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+        self.updated = true;
+    }
+
+    /// Changes the value in `f`, which returns whether it actually changed
+    /// anything. Unlike going through `DerefMut`, it's only marked updated
+    /// if so. Returns what `f` did.
+    pub fn modify(&mut self, f: impl FnOnce(&mut T) -> bool) -> bool {
+        let changed = f(&mut self.value);
+        self.updated |= changed;
+        changed
+    }
+
     pub fn handle_updated(&mut self) -> Option<ResetGuard<T>> {
         if self.updated {
             Some(ResetGuard { memoized: self })
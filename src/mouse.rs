@@ -1,6 +1,6 @@
 #[derive(Clone)]
 pub struct Mouse {
-    /// Mouse position in screen coordinates
+    /// Mouse position in physical pixels, like the surface size
     pub pos: Option<cgmath::Vector2<f32>>,
     pub last_pos: cgmath::Vector2<f32>,
     pub clicked: bool,
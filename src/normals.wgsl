@@ -0,0 +1,29 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+
+@binding(0) @group(0) var<uniform> camera: Camera;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>, @builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera.view_proj * vec4<f32>(pos, 1.0);
+    // Even vertices sit on the particle, odd ones at the tip of its normal
+    if (in_vertex_index % 2u == 0u) {
+        out.color = vec3<f32>(0.0, 0.0, 1.0);
+    } else {
+        out.color = vec3<f32>(1.0, 1.0, 0.0);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
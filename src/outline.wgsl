@@ -0,0 +1,31 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+
+@binding(0) @group(0) var<uniform> camera: Camera;
+
+struct Outline {
+    color: vec4<f32>,
+    center: vec3<f32>,
+    width: f32,
+}
+
+@binding(0) @group(1) var<uniform> outline: Outline;
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>) -> @builtin(position) vec4<f32> {
+    // Push every vertex away from the middle of the cloth so the copy pokes
+    // out past its edges
+    let offset = pos - outline.center;
+    var grown = pos;
+    if (length(offset) > 0.0) {
+        grown = pos + normalize(offset) * outline.width;
+    }
+    return camera.view_proj * vec4<f32>(grown, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
@@ -0,0 +1,246 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+/// How many screen pixels wide one pixel of the font is, before the window's
+/// scale factor.
+const PIXEL_SIZE: f32 = 3.0;
+/// Distance from the top left corner of the screen to the text, in font pixels.
+const MARGIN: f32 = 3.0;
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// A 3x5 bitmap of `c`, one row per byte with the leftmost pixel in bit 2.
+/// Only what the stats readout needs is drawn, anything else is blank.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        _ => [0; 5],
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct OverlayVertex {
+    /// In physical pixels from the top left corner.
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl OverlayVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct OverlayUniform {
+    screen_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+/// A few lines of blocky text in the top left corner, drawn over the
+/// finished frame, e.g. the frame rate.
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: Option<(wgpu::Buffer, u32)>,
+    pub visible: bool,
+}
+
+impl Overlay {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("overlay.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overlay bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let uniform = OverlayUniform {
+            screen_size: [config.width as f32, config.height as f32],
+            _pad: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overlay bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay render pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[OverlayVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            // Drawn straight onto the resolved surface, after post-processing
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer: None,
+            visible: false,
+        }
+    }
+
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        let uniform = OverlayUniform {
+            screen_size: [width as f32, height as f32],
+            _pad: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Replaces the text, one line per `\n`. `scale_factor` is the window's,
+    /// so the text stays the same size on high-DPI screens.
+    pub fn set_text(&mut self, device: &wgpu::Device, text: &str, scale_factor: f32) {
+        let pixel = PIXEL_SIZE * scale_factor;
+        let mut vertices = Vec::new();
+        let mut push_rect = |x: f32, y: f32, w: f32, h: f32, color: [f32; 4]| {
+            let corners = [
+                [x, y],
+                [x, y + h],
+                [x + w, y],
+                [x + w, y],
+                [x, y + h],
+                [x + w, y + h],
+            ];
+            vertices.extend(corners.map(|position| OverlayVertex { position, color }));
+        };
+
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let rows = text.lines().count();
+        if let Some(columns) = columns.filter(|&columns| columns > 0) {
+            // Each glyph is 3x5 with one pixel of spacing on either axis
+            push_rect(
+                (MARGIN - 1.0) * pixel,
+                (MARGIN - 1.0) * pixel,
+                (columns as f32 * 4.0 + 1.0) * pixel,
+                (rows as f32 * 6.0 + 1.0) * pixel,
+                BACKDROP_COLOR,
+            );
+        }
+        for (row, line) in text.lines().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let left = MARGIN + column as f32 * 4.0;
+                let top = MARGIN + row as f32 * 6.0;
+                for (y, bits) in glyph(c).iter().enumerate() {
+                    for x in 0..3 {
+                        if bits & (0b100 >> x) != 0 {
+                            push_rect(
+                                (left + x as f32) * pixel,
+                                (top + y as f32) * pixel,
+                                pixel,
+                                pixel,
+                                TEXT_COLOR,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        self.vertex_buffer = if vertices.is_empty() {
+            None
+        } else {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Overlay Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            Some((buffer, vertices.len() as u32))
+        };
+    }
+
+    /// Draws the text on top of whatever `target` already holds.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let (vertex_buffer, vertex_count) = match self.vertex_buffer.as_ref() {
+            Some(vertex_buffer) if self.visible => vertex_buffer,
+            _ => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..*vertex_count, 0..1);
+    }
+}
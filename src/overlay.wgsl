@@ -0,0 +1,26 @@
+struct Overlay {
+    screen_size: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> overlay: Overlay;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// Positions come in pixels from the top left corner
+@vertex
+fn vs_main(@location(0) position: vec2<f32>, @location(1) color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = position / overlay.screen_size * 2.0 - vec2<f32>(1.0, 1.0);
+    out.position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -16,6 +16,7 @@ struct VertexOutput {
     @location(0) normal: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) in_vertex_index: u32,
+    @location(3) world_pos: vec3<f32>,
 }
 
 @vertex
@@ -28,6 +29,7 @@ fn vs_main(in: VertexInput, @builtin(vertex_index) in_vertex_index: u32) -> Vert
     out.position = pos;
     out.tex_coords = in.tex_coord;
     out.in_vertex_index = in_vertex_index;
+    out.world_pos = in.pos;
 
     return out;
 }
@@ -38,14 +40,70 @@ var t_diffuse: texture_2d<f32>;
 @fragment @group(1) @binding(1)
 var s_diffuse: sampler;
 
+struct Shading {
+    letterbox_color: vec4<f32>,
+    letterbox: u32,
+    reflectivity: f32,
+    double_sided: u32,
+    flat_shading: u32,
+    backface_color: vec4<f32>,
+    backface: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> shading: Shading;
+
+struct Lighting {
+    direction: vec3<f32>,
+    intensity: f32,
+    ambient: f32,
+}
+
+@group(2) @binding(1)
+var<uniform> lighting: Lighting;
+
+@group(3) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(3) @binding(1)
+var s_environment: sampler;
+
 @fragment
 fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
-    var ret: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 1.0);
-    let world_normal = normalize(vec3<f32>(50.0, 6.0, 50.0));
-    // let world_normal = normalize(vec3<f32>(camera.view_pos.xyz));
-    let min = 0.0;
-    let diffuse_strength = max(dot(in.normal, world_normal), min);
+    // Vertices are shared between triangles, so the per-triangle normal for
+    // flat shading comes from how the position changes across the triangle.
+    // Derivatives need uniform control flow, so this goes first.
+    let face_normal = cross(dpdx(in.world_pos), dpdy(in.world_pos));
+    var ret: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let outside = any(in.tex_coords < vec2<f32>(0.0, 0.0)) || any(in.tex_coords > vec2<f32>(1.0, 1.0));
+    if (shading.letterbox != 0u && outside) {
+        ret = shading.letterbox_color;
+    }
+    if (shading.backface != 0u && !front_facing) {
+        ret = shading.backface_color;
+    }
+    let view_dir = normalize(in.world_pos - camera.view_pos.xyz);
+    var normal = normalize(in.normal);
+    if (shading.flat_shading != 0u && length(face_normal) > 0.0) {
+        // Keep it on the same side as the smooth normal
+        var flat_normal = normalize(face_normal);
+        if (dot(flat_normal, normal) < 0.0) {
+            flat_normal = -flat_normal;
+        }
+        normal = flat_normal;
+    }
+    // There's one normal per particle, so seen from behind the paper it
+    // points away from the camera. Flip it so the underside is lit too.
+    if (shading.double_sided != 0u && dot(normal, view_dir) > 0.0) {
+        normal = -normal;
+    }
+
+    let diffuse_strength = lighting.ambient
+        + lighting.intensity * max(dot(normal, lighting.direction), 0.0);
     var color: vec3<f32> = vec3<f32>(ret.xyz) * diffuse_strength;
+
+    let reflected = reflect(view_dir, normal);
+    let environment = textureSample(t_environment, s_environment, reflected).xyz;
+    color = mix(color, environment, shading.reflectivity);
     // if (in.in_vertex_index <= 2u) {
     //     color = vec3<f32>(1.0, 0.0, 0.0);
     // }
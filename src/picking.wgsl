@@ -0,0 +1,26 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+
+@binding(0) @group(0) var<uniform> camera: Camera;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+}
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>, @builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera.view_proj * vec4<f32>(pos, 1.0);
+    // One vertex per particle, 0 is left for "nothing". Flat interpolation
+    // gives every pixel of a triangle its first corner's ID.
+    out.id = in_vertex_index + 1u;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
@@ -0,0 +1,514 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+
+/// Builds a pipeline that draws a single full-screen triangle with no vertex
+/// buffers, for passes that read one texture and write another.
+fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fs_entry_point: &str,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fs_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+    texel_size: [f32; 2],
+}
+
+struct BloomTargets {
+    scene: Texture,
+    bright: Texture,
+    blur: Texture,
+    extract_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup,
+    blur_vertical_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+}
+
+/// Makes bright parts of the frame glow: the scene is rendered offscreen,
+/// its highlights are extracted and blurred at half resolution, then added
+/// back on top.
+pub struct Bloom {
+    bind_group_layout: wgpu::BindGroupLayout,
+    extract_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    uniform: BloomUniform,
+    uniform_buffer: wgpu::Buffer,
+    targets: BloomTargets,
+}
+
+impl Bloom {
+    pub const DEFAULT_THRESHOLD: f32 = 0.8;
+    pub const DEFAULT_INTENSITY: f32 = 1.0;
+
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("bloom.wgsl").into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom bind group layout"),
+            entries: &[
+                texture_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, entry_point| {
+            create_fullscreen_pipeline(
+                device,
+                label,
+                &pipeline_layout,
+                &shader,
+                entry_point,
+                config.format,
+            )
+        };
+        let extract_pipeline = pipeline("Bloom extract pipeline", "fs_extract");
+        let blur_horizontal_pipeline = pipeline("Bloom blur pipeline", "fs_blur_horizontal");
+        let blur_vertical_pipeline = pipeline("Bloom blur pipeline", "fs_blur_vertical");
+        let composite_pipeline = pipeline("Bloom composite pipeline", "fs_composite");
+
+        let uniform = BloomUniform {
+            threshold: Self::DEFAULT_THRESHOLD,
+            intensity: Self::DEFAULT_INTENSITY,
+            texel_size: Self::texel_size(config),
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let targets = Self::create_targets(device, config, &bind_group_layout, &uniform_buffer);
+
+        Self {
+            bind_group_layout,
+            extract_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            composite_pipeline,
+            uniform,
+            uniform_buffer,
+            targets,
+        }
+    }
+
+    fn texel_size(config: &wgpu::SurfaceConfiguration) -> [f32; 2] {
+        [
+            1.0 / (config.width / 2).max(1) as f32,
+            1.0 / (config.height / 2).max(1) as f32,
+        ]
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> BloomTargets {
+        let (width, height) = (config.width, config.height);
+        let scene =
+            Texture::create_render_target(device, width, height, config.format, "Bloom scene");
+        let bright = Texture::create_render_target(
+            device,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            config.format,
+            "Bloom bright",
+        );
+        let blur = Texture::create_render_target(
+            device,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            config.format,
+            "Bloom blur",
+        );
+
+        let bind_group = |source: &Texture, bloom: &Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Bloom bind group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(
+                            source.sampler.as_ref().expect("Texture to have sampler"),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&bloom.view),
+                    },
+                ],
+            })
+        };
+
+        BloomTargets {
+            // The unused glow slot just needs a texture that isn't being rendered to
+            extract_bind_group: bind_group(&scene, &scene),
+            blur_horizontal_bind_group: bind_group(&bright, &bright),
+            blur_vertical_bind_group: bind_group(&blur, &blur),
+            composite_bind_group: bind_group(&scene, &bright),
+            scene,
+            bright,
+            blur,
+        }
+    }
+
+    /// The offscreen texture the scene should be rendered into.
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.targets.scene.view
+    }
+
+    pub fn set_params(&mut self, queue: &wgpu::Queue, threshold: f32, intensity: f32) {
+        self.uniform.threshold = threshold;
+        self.uniform.intensity = intensity;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.targets = Self::create_targets(
+            device,
+            config,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+        );
+        self.uniform.texel_size = Self::texel_size(config);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Runs the bloom passes over the rendered scene and writes the result to `target`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let targets = &self.targets;
+        fullscreen_pass(
+            encoder,
+            "Bloom extract pass",
+            &targets.bright.view,
+            &self.extract_pipeline,
+            &targets.extract_bind_group,
+        );
+        fullscreen_pass(
+            encoder,
+            "Bloom blur pass",
+            &targets.blur.view,
+            &self.blur_horizontal_pipeline,
+            &targets.blur_horizontal_bind_group,
+        );
+        fullscreen_pass(
+            encoder,
+            "Bloom blur pass",
+            &targets.bright.view,
+            &self.blur_vertical_pipeline,
+            &targets.blur_vertical_bind_group,
+        );
+        fullscreen_pass(
+            encoder,
+            "Bloom composite pass",
+            target,
+            &self.composite_pipeline,
+            &targets.composite_bind_group,
+        );
+    }
+}
+
+/// How the main pass smooths jagged edges.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AaMode {
+    /// Multisample the main pass with the given sample count.
+    Msaa(u32),
+    /// Render without multisampling and run an FXAA pass afterwards.
+    Fxaa,
+    None,
+}
+
+impl AaMode {
+    pub fn sample_count(&self) -> u32 {
+        match self {
+            AaMode::Msaa(n) => (*n).max(1),
+            AaMode::Fxaa | AaMode::None => 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct FxaaUniform {
+    texel_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+impl FxaaUniform {
+    fn new(config: &wgpu::SurfaceConfiguration) -> Self {
+        Self {
+            texel_size: [1.0 / config.width as f32, 1.0 / config.height as f32],
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+/// Fast approximate anti-aliasing, a screen-space alternative to MSAA that
+/// smooths edges after the scene has been rendered without multisampling.
+pub struct Fxaa {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    input: Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("fxaa.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("FXAA bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("FXAA pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            "FXAA pipeline",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            config.format,
+        );
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("FXAA Buffer"),
+            contents: bytemuck::cast_slice(&[FxaaUniform::new(config)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (input, bind_group) =
+            Self::create_input(device, config, &bind_group_layout, &uniform_buffer);
+
+        Self {
+            bind_group_layout,
+            pipeline,
+            uniform_buffer,
+            input,
+            bind_group,
+        }
+    }
+
+    fn create_input(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> (Texture, wgpu::BindGroup) {
+        let input = Texture::create_render_target(
+            device,
+            config.width,
+            config.height,
+            config.format,
+            "FXAA input",
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("FXAA bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        input.sampler.as_ref().expect("Texture to have sampler"),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (input, bind_group)
+    }
+
+    /// The offscreen texture the anti-aliased image is read from.
+    pub fn input_view(&self) -> &wgpu::TextureView {
+        &self.input.view
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        let (input, bind_group) = Self::create_input(
+            device,
+            config,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+        );
+        self.input = input;
+        self.bind_group = bind_group;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[FxaaUniform::new(config)]),
+        );
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        fullscreen_pass(
+            encoder,
+            "FXAA pass",
+            target,
+            &self.pipeline,
+            &self.bind_group,
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use bytemuck::Zeroable;
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{texture::Texture, Vertex};
+
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Normalized
+    pub dir: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, dir: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.dir * t
+    }
+
+    /// Möller–Trumbore ray/triangle test, returns the distance along the ray
+    /// to the hit. Triangles are hit from either side.
+    pub fn triangle_intersection(&self, triangle: [Point3<f32>; 3]) -> Option<f32> {
+        const EPSILON: f32 = 1e-7;
+
+        let [v0, v1, v2] = triangle;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+
+        let h = self.dir.cross(edge2);
+        let a = edge1.dot(h);
+        // Parallel to the triangle
+        if a.abs() < EPSILON {
+            return None;
+        }
+
+        let f = 1.0 / a;
+        let s = self.origin - Vector3::new(v0.x, v0.y, v0.z);
+        let u = f * s.dot(h);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = f * self.dir.dot(q);
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = f * edge2.dot(q);
+        if t > EPSILON {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+/// Draws the last picking ray as a line, from the near plane through the
+/// cursor to the far plane, for seeing where picks go. It sits on top of
+/// everything, and shows up as a dot until the camera moves off it.
+pub struct RayPipeline {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    /// Set once the first ray comes in.
+    has_ray: bool,
+    pub enabled: bool,
+}
+
+impl RayPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ray shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ray.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ray pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline =
+            Self::create_pipeline(device, &pipeline_layout, &shader, format, sample_count);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ray vertex buffer"),
+            contents: bytemuck::cast_slice(&[Vertex::zeroed(); 2]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            shader,
+            pipeline_layout,
+            format,
+            sample_count,
+            pipeline,
+            vertex_buffer,
+            has_ray: false,
+            enabled: false,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ray render pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Never hidden, a ray that misses behind the cloth still shows
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.pipeline = Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.shader,
+                self.format,
+                self.sample_count,
+            );
+        }
+    }
+
+    /// Draws a line from `start` to `end` from now on.
+    pub fn update(&mut self, queue: &wgpu::Queue, start: Vector3<f32>, end: Vector3<f32>) {
+        let vertices = [
+            Vertex {
+                position: start.into(),
+            },
+            Vertex {
+                position: end.into(),
+            },
+        ];
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.has_ray = true;
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if !self.enabled || !self.has_ray {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..2, 0..1);
+    }
+}
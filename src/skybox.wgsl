@@ -0,0 +1,37 @@
+struct Skybox {
+    inv_view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var t_skybox: texture_cube<f32>;
+@group(0) @binding(1)
+var s_skybox: sampler;
+@group(0) @binding(2)
+var<uniform> skybox: Skybox;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let x = f32((in_vertex_index << 1u) & 2u) * 2.0 - 1.0;
+    let y = f32(in_vertex_index & 2u) * 2.0 - 1.0;
+
+    out.position = vec4<f32>(x, y, 1.0, 1.0);
+    out.ndc = vec2<f32>(x, y);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Unproject the far plane with a translation-free view, so only the
+    // camera's rotation affects which part of the cube we see
+    let world = skybox.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(world.xyz / world.w);
+    return textureSample(t_skybox, s_skybox, dir);
+}
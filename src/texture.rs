@@ -1,16 +1,130 @@
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU8};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
 
 use anyhow::*;
+use cgmath::Vector4;
 // use image::GenericImageView;
 
+/// Where to load an image from, see `Texture::from_source`.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    /// An encoded PNG or JPEG.
+    Bytes(Vec<u8>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Path(PathBuf),
+}
+
+impl ImageSource {
+    /// The encoded image, read from disk if need be.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            ImageSource::Bytes(bytes) => Ok(bytes.clone()),
+            #[cfg(not(target_arch = "wasm32"))]
+            ImageSource::Path(path) => std::fs::read(path)
+                .with_context(|| format!("Failed to read image {}", path.display())),
+        }
+    }
+}
+
+/// How an image texture is sampled, see `Texture::from_bytes`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureOptions {
+    /// Filtering where the texture is drawn smaller than it is.
+    pub min_filter: wgpu::FilterMode,
+    /// Filtering where it's drawn larger, `Nearest` keeps pixel art crisp.
+    pub mag_filter: wgpu::FilterMode,
+    pub address_mode: wgpu::AddressMode,
+    /// Downsamples the image into a full chain of mip levels when loading it
+    /// so it doesn't shimmer from far away or at a steep angle.
+    pub generate_mipmaps: bool,
+    /// Samples taken along the slope of a surface seen at a grazing angle,
+    /// which keeps a folded tweet sharp. 1 turns it off, higher values are
+    /// rounded down to 2, 4, 8 or 16. It needs both filters `Linear` and
+    /// `DownlevelFlags::ANISOTROPIC_FILTERING`, which WebGL lacks.
+    pub anisotropy: u16,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: wgpu::FilterMode::Linear,
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            generate_mipmaps: true,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Nearest-neighbor filtering, for blocky pixel art.
+    pub fn nearest() -> Self {
+        Self {
+            min_filter: wgpu::FilterMode::Nearest,
+            mag_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        }
+    }
+
+    fn sampler_descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        // wgpu only allows anisotropy with every filter linear
+        let linear = self.min_filter == wgpu::FilterMode::Linear
+            && self.mag_filter == wgpu::FilterMode::Linear;
+        let anisotropy_clamp = if linear && self.anisotropy > 1 {
+            let clamp = self.anisotropy.min(16);
+            NonZeroU8::new(1 << (15 - clamp.leading_zeros()))
+        } else {
+            None
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+}
+
+/// How the depth attachment is cleared and how fragments are tested against it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthConfig {
+    pub clear: f32,
+    pub compare: wgpu::CompareFunction,
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        Self {
+            clear: 1.0,
+            compare: wgpu::CompareFunction::LessEqual,
+        }
+    }
+}
+
+impl DepthConfig {
+    /// The settings to use with a reverse-Z projection.
+    pub fn reverse_z() -> Self {
+        Self {
+            clear: 0.0,
+            compare: wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: Option<wgpu::Sampler>,
+    pub size: wgpu::Extent3d,
 }
 
 impl Texture {
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
 
     pub fn create(
         device: &wgpu::Device,
@@ -40,6 +154,48 @@ impl Texture {
             texture,
             view,
             sampler: None,
+            size,
+        }
+    }
+
+    /// A single-sampled color target that can also be sampled by a later pass.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+            size,
         }
     }
 
@@ -83,6 +239,7 @@ impl Texture {
             texture,
             view,
             sampler: None,
+            size,
         }
     }
 
@@ -91,20 +248,130 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        options: TextureOptions,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), options)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &Path,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read image {}", path.display()))?;
+        Self::from_bytes(device, queue, &bytes, &path.display().to_string(), options)
+    }
+
+    /// Downloads the encoded image at `url`, to pass to `from_bytes`.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+
+        let window = web_sys::window().context("No window to fetch from")?;
+        let response = JsFuture::from(window.fetch_with_str(url))
+            .await
+            .map_err(|err| anyhow!("Failed to fetch {}: {:?}", url, err))?;
+        let response: web_sys::Response = response
+            .dyn_into()
+            .map_err(|_| anyhow!("Fetching {} didn't return a response", url))?;
+        if !response.ok() {
+            bail!("Failed to fetch {}: HTTP {}", url, response.status());
+        }
+        let buffer = response
+            .array_buffer()
+            .map_err(|err| anyhow!("Failed to read {}: {:?}", url, err))?;
+        let buffer = JsFuture::from(buffer)
+            .await
+            .map_err(|err| anyhow!("Failed to read {}: {:?}", url, err))?;
+        Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+    }
+
+    pub fn from_source(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &ImageSource,
+        label: &str,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        match source {
+            ImageSource::Bytes(bytes) => Self::from_bytes(device, queue, bytes, label, options),
+            #[cfg(not(target_arch = "wasm32"))]
+            ImageSource::Path(path) => Self::from_path(device, queue, path, options),
+        }
     }
 
+    /// Uploads `img`, with a full chain of mip levels downsampled on the CPU
+    /// if `options` ask for them.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        options: TextureOptions,
     ) -> Result<Self> {
-        use image::GenericImageView;
         let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Ok(Self::upload_rgba(
+            device,
+            queue,
+            (rgba.width(), rgba.height()),
+            &rgba,
+            label,
+            options,
+        ))
+    }
+
+    /// Uploads already decoded pixels, 8-bit sRGB RGBA row by row from the
+    /// top left, e.g. a tweet rendered by the embedder.
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        label: &str,
+        options: TextureOptions,
+    ) -> Result<Self> {
+        let expected = width as usize * height as usize * 4;
+        if rgba.len() != expected {
+            bail!(
+                "Expected {} bytes of RGBA for {}x{}, got {}",
+                expected,
+                width,
+                height,
+                rgba.len()
+            );
+        }
+        if width == 0 || height == 0 {
+            bail!("Can't make an empty {}x{} texture", width, height);
+        }
+        Ok(Self::upload_rgba(
+            device,
+            queue,
+            (width, height),
+            rgba,
+            Some(label),
+            options,
+        ))
+    }
+
+    fn upload_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dimensions: (u32, u32),
+        rgba: &[u8],
+        label: Option<&str>,
+        options: TextureOptions,
+    ) -> Self {
+        let mip_level_count = if options.generate_mipmaps {
+            32 - dimensions.0.max(dimensions.1).leading_zeros()
+        } else {
+            1
+        };
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -114,7 +381,7 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
@@ -128,7 +395,7 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: NonZeroU32::new(4 * dimensions.0),
@@ -137,21 +404,199 @@ impl Texture {
             size,
         );
 
+        // Each level halves the one before it, down to 1x1. Averaging the
+        // sRGB bytes as they are would darken the smaller levels, so they're
+        // filtered in linear space and encoded back to sRGB for upload.
+        if mip_level_count > 1 {
+            let linear = rgba
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let color = crate::srgb_to_linear(
+                        Vector4::new(
+                            pixel[0] as f32,
+                            pixel[1] as f32,
+                            pixel[2] as f32,
+                            pixel[3] as f32,
+                        ) / 255.0,
+                    );
+                    [color.x, color.y, color.z, color.w]
+                })
+                .collect();
+            let mut level = image::Rgba32FImage::from_raw(dimensions.0, dimensions.1, linear)
+                .expect("RGBA size checked by the caller");
+            for mip_level in 1..mip_level_count {
+                let width = (level.width() / 2).max(1);
+                let height = (level.height() / 2).max(1);
+                level = image::imageops::resize(
+                    &level,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                );
+                let encoded: Vec<u8> = level
+                    .pixels()
+                    .flat_map(|pixel| {
+                        let color = crate::linear_to_srgb(Vector4::from(pixel.0));
+                        [color.x, color.y, color.z, color.w]
+                            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+                    })
+                    .collect();
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    &encoded,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(4 * width),
+                        rows_per_image: NonZeroU32::new(height),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&options.sampler_descriptor());
+
+        Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+            size,
+        }
+    }
+
+    /// Builds a cube texture from six square faces in `+X, -X, +Y, -Y, +Z, -Z` order.
+    pub fn create_cubemap(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&[u8]; 6],
+        label: &str,
+    ) -> Result<Self> {
+        use image::GenericImageView;
+        let faces = faces
+            .iter()
+            .map(|bytes| image::load_from_memory(bytes))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let (width, height) = faces[0].dimensions();
+        if width != height {
+            bail!("cubemap faces must be square, got {}x{}", width, height);
+        }
+        if faces
+            .iter()
+            .any(|face| face.dimensions() != (width, height))
+        {
+            bail!("cubemap faces must all be the same size");
+        }
+
+        let faces = faces
+            .iter()
+            .map(|face| face.to_rgba8().into_raw())
+            .collect::<Vec<_>>();
+        Ok(Self::create_cubemap_from_rgba(
+            device,
+            queue,
+            width,
+            [
+                &faces[0][..],
+                &faces[1][..],
+                &faces[2][..],
+                &faces[3][..],
+                &faces[4][..],
+                &faces[5][..],
+            ],
+            label,
+        ))
+    }
+
+    /// Builds a cube texture from six `size`x`size` RGBA8 faces.
+    pub fn create_cubemap_from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+        faces: [&[u8]; 6],
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                face,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(4 * size.width),
+                    rows_per_image: NonZeroU32::new(size.height),
+                },
+                wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler: Some(sampler),
-        })
+            size,
+        }
+    }
+
+    /// Recreates the sampler with different filtering or address mode, e.g.
+    /// `Repeat` for tiling. Mip levels are only made when loading, so
+    /// `generate_mipmaps` is ignored.
+    pub fn set_options(&mut self, device: &wgpu::Device, options: TextureOptions) {
+        self.sampler = Some(device.create_sampler(&options.sampler_descriptor()));
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.size.width as f32 / self.size.height as f32
     }
 }
@@ -0,0 +1,337 @@
+use cgmath::{vec4, Vector3};
+use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use crate::{
+    cloth::{MAX_TIME_SCALE, MIN_TIME_SCALE},
+    main_state::State,
+};
+
+/// How many points one line of mouse wheel scrolls the panel.
+const SCROLL_LINE: f32 = 50.0;
+
+/// An egui side panel for changing the simulation while it runs, drawn over
+/// the finished frame. Events are translated here rather than with
+/// egui-winit, which would pull in a second copy of winit.
+pub struct Ui {
+    context: egui::Context,
+    renderer: Renderer,
+    /// Input gathered since the last frame started.
+    input: egui::RawInput,
+    pointer_pos: egui::Pos2,
+    start: instant::Instant,
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pub visible: bool,
+}
+
+impl Ui {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            context: egui::Context::default(),
+            // Drawn straight onto the resolved surface, like the overlay
+            renderer: Renderer::new(device, format, None, 1),
+            input: egui::RawInput::default(),
+            pointer_pos: egui::Pos2::ZERO,
+            start: instant::Instant::now(),
+            paint_jobs: vec![],
+            textures_delta: egui::TexturesDelta::default(),
+            visible: true,
+        }
+    }
+
+    /// Passes `event` on to egui. Returns whether egui wants it for itself,
+    /// in which case the cloth and camera shouldn't see it. Releases are
+    /// never taken, so a drag that ends over the panel still ends.
+    pub fn on_event(&mut self, event: &WindowEvent, pixels_per_point: f32) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let wants_pointer = self.wants_pointer();
+        let wants_keyboard = self.context.wants_keyboard_input();
+        let modifiers = self.input.modifiers;
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer_pos = egui::pos2(
+                    position.x as f32 / pixels_per_point,
+                    position.y as f32 / pixels_per_point,
+                );
+                self.input
+                    .events
+                    .push(egui::Event::PointerMoved(self.pointer_pos));
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                let pressed = *state == ElementState::Pressed;
+                self.input.events.push(egui::Event::PointerButton {
+                    pos: self.pointer_pos,
+                    button,
+                    pressed,
+                    modifiers,
+                });
+                pressed && wants_pointer
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => egui::vec2(*x, *y) * SCROLL_LINE,
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        egui::vec2(delta.x as f32, delta.y as f32) / pixels_per_point
+                    }
+                };
+                self.input.events.push(egui::Event::Scroll(delta));
+                wants_pointer
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                self.input.modifiers = egui::Modifiers {
+                    alt: state.alt(),
+                    ctrl: state.ctrl(),
+                    shift: state.shift(),
+                    mac_cmd: cfg!(target_os = "macos") && state.logo(),
+                    command: if cfg!(target_os = "macos") {
+                        state.logo()
+                    } else {
+                        state.ctrl()
+                    },
+                };
+                false
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                if !c.is_control() {
+                    self.input.events.push(egui::Event::Text(c.to_string()));
+                }
+                wants_keyboard
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(key) = egui_key(*key) {
+                    self.input.events.push(egui::Event::Key {
+                        key,
+                        pressed: *state == ElementState::Pressed,
+                        modifiers,
+                    });
+                }
+                wants_keyboard && *state == ElementState::Pressed
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the pointer is over the panel or busy with one of its widgets.
+    /// Presses and motion that only arrive as device events, which never go
+    /// through `on_event`, should check this before reaching the cloth.
+    pub fn wants_pointer(&self) -> bool {
+        self.visible && self.context.wants_pointer_input()
+    }
+
+    /// Starts a frame of the given size in physical pixels, `None` while the
+    /// panel is hidden. Lay it out with `controls`, then call `end_frame`.
+    pub(crate) fn begin_frame(
+        &mut self,
+        size: [u32; 2],
+        pixels_per_point: f32,
+    ) -> Option<egui::Context> {
+        if !self.visible {
+            self.paint_jobs.clear();
+            return None;
+        }
+        let mut input = std::mem::take(&mut self.input);
+        // Held modifiers outlast the frame
+        self.input.modifiers = input.modifiers;
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(size[0] as f32, size[1] as f32) / pixels_per_point,
+        ));
+        input.pixels_per_point = Some(pixels_per_point);
+        input.time = Some(self.start.elapsed().as_secs_f64());
+
+        self.context.begin_frame(input);
+        Some(self.context.clone())
+    }
+
+    /// Finishes the frame `begin_frame` started and gets it ready for `render`.
+    pub(crate) fn end_frame(&mut self) {
+        let output = self.context.end_frame();
+        self.paint_jobs = self.context.tessellate(output.shapes);
+        self.textures_delta.append(output.textures_delta);
+    }
+
+    /// Draws the panel on top of whatever `target` already holds. The
+    /// returned command buffers upload its vertices and have to be submitted
+    /// before `encoder`.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        size: [u32; 2],
+        pixels_per_point: f32,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let textures_delta = std::mem::take(&mut self.textures_delta);
+        for (id, image_delta) in textures_delta.set.iter() {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: size,
+            pixels_per_point,
+        };
+        let command_buffers = self.renderer.update_buffers(
+            device,
+            queue,
+            encoder,
+            &self.paint_jobs,
+            &screen_descriptor,
+        );
+        if !self.paint_jobs.is_empty() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer
+                .render(&mut render_pass, &self.paint_jobs, &screen_descriptor);
+        }
+
+        for id in textures_delta.free.iter() {
+            self.renderer.free_texture(id);
+        }
+        command_buffers
+    }
+}
+
+/// The panel itself. Every control reads the current value back each frame,
+/// so changes made elsewhere, e.g. by key bindings, show up in it.
+pub(crate) fn controls(context: &egui::Context, state: &mut State) {
+    egui::SidePanel::right("controls").show(context, |ui| {
+        ui.heading("Cloth");
+        {
+            let mut cloth = state.physics.cloth();
+
+            let mut gravity = cloth.gravity();
+            if vector_edit(ui, "Gravity", &mut gravity) {
+                cloth.set_gravity(gravity);
+            }
+
+            let mut wind = cloth.wind();
+            if vector_edit(ui, "Wind", &mut wind) {
+                cloth.set_wind(wind);
+            }
+
+            let mut damping = cloth.damping();
+            if ui
+                .add(egui::Slider::new(&mut damping, 0.0..=0.2).text("Damping"))
+                .changed()
+            {
+                cloth.set_damping(damping);
+            }
+
+            let mut iterations = cloth.constraint_iterations();
+            if ui
+                .add(egui::Slider::new(&mut iterations, 1..=30).text("Iterations"))
+                .changed()
+            {
+                cloth.set_constraint_iterations(iterations);
+            }
+        }
+
+        let mut time_scale = state.physics.time_scale();
+        if ui
+            .add(
+                egui::Slider::new(&mut time_scale, MIN_TIME_SCALE..=MAX_TIME_SCALE)
+                    .logarithmic(true)
+                    .text("Time scale"),
+            )
+            .changed()
+        {
+            state.physics.set_time_scale(time_scale);
+        }
+
+        ui.separator();
+        ui.heading("View");
+        let mut msaa = state.msaa();
+        egui::ComboBox::from_label("MSAA")
+            .selected_text(format!("{}x", msaa))
+            .show_ui(ui, |ui| {
+                for &count in state.msaa_sample_counts() {
+                    ui.selectable_value(&mut msaa, count, format!("{}x", count));
+                }
+            });
+        if msaa != state.msaa() {
+            state.set_msaa(msaa);
+        }
+
+        ui.horizontal(|ui| {
+            // Both sides of this are linear, like `State::bg`
+            let mut background = [state.bg.x, state.bg.y, state.bg.z];
+            if ui.color_edit_button_rgb(&mut background).changed() {
+                let color = vec4(background[0], background[1], background[2], state.bg.w);
+                state.set_background(color, false);
+            }
+            ui.label("Background");
+        });
+    });
+}
+
+/// Three drag values side by side, returns whether any of them changed.
+fn vector_edit(ui: &mut egui::Ui, label: &str, vector: &mut Vector3<f32>) -> bool {
+    ui.horizontal(|ui| {
+        let mut changed = false;
+        for value in [&mut vector.x, &mut vector.y, &mut vector.z] {
+            changed |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+        }
+        ui.label(label);
+        changed
+    })
+    .inner
+}
+
+/// The keys text fields need, anything else is left to the key bindings.
+fn egui_key(key: VirtualKeyCode) -> Option<egui::Key> {
+    Some(match key {
+        VirtualKeyCode::Back => egui::Key::Backspace,
+        VirtualKeyCode::Delete => egui::Key::Delete,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => egui::Key::Enter,
+        VirtualKeyCode::Escape => egui::Key::Escape,
+        VirtualKeyCode::Tab => egui::Key::Tab,
+        VirtualKeyCode::Left => egui::Key::ArrowLeft,
+        VirtualKeyCode::Right => egui::Key::ArrowRight,
+        VirtualKeyCode::Up => egui::Key::ArrowUp,
+        VirtualKeyCode::Down => egui::Key::ArrowDown,
+        VirtualKeyCode::Home => egui::Key::Home,
+        VirtualKeyCode::End => egui::Key::End,
+        VirtualKeyCode::A => egui::Key::A,
+        VirtualKeyCode::C => egui::Key::C,
+        VirtualKeyCode::V => egui::Key::V,
+        VirtualKeyCode::X => egui::Key::X,
+        VirtualKeyCode::Z => egui::Key::Z,
+        _ => return None,
+    })
+}